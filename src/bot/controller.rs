@@ -1,22 +1,55 @@
-use std::collections::HashMap;
+use std::{sync::Arc, time::Duration};
 
-use teloxide::{types::{ChatId, Message}, Bot, RequestError};
+use chrono::Utc;
+use futures::{stream, StreamExt};
+use teloxide::{
+    prelude::Requester,
+    types::{CallbackQuery, Chat, ChatId, InlineKeyboardMarkup, Me, Message, MessageId, UserId},
+    utils::command::BotCommands,
+    Bot, DownloadError, RequestError,
+};
 use thiserror::Error;
-use tokio::sync::Mutex;
+use tokio::{
+    sync::mpsc,
+    time::Instant,
+};
 
 use crate::{
-    config::Config,
-    nova::{helpers as nova_helpers, NovaClient, NovaClientError},
-    utils::{self, TypingIndicator},
+    config::{dto::{ConversationMode, ImageMode, ThreadPolicy}, Config},
+    metrics::METRICS,
+    nova::{dto::NovaRequest, helpers as nova_helpers, NovaClient, NovaClientError, NovaResponse},
+    store::{ChatStateStore, StoreError},
+    utils::{self, Destination, TypingIndicator},
 };
 
-use super::{dto::{BotCommand, ChatState}, helpers};
+use super::{
+    dto::{
+        AccessDecision, AnswerAction, AnsweredPrompt, BotCommand, CallbackAction, ChatState, ConversationError,
+        Conversations, Persona, QuotaPeriod, SettingChange, UsageScope, DEFAULT_CONVERSATION,
+    },
+    helpers,
+    rate_limit::RateLimiter,
+};
+
+/// How an answer is posted to the chat.
+enum Delivery {
+    /// As new replies to the prompt.
+    Reply,
+    /// In place of an earlier answer, sent in these messages.
+    Replace(Vec<MessageId>),
+}
+
+/// Longest custom persona prompt accepted by `/persona`, in characters.
+const MAX_PERSONA_PROMPT_CHARS: usize = 2000;
+/// How many histories `/delete` clears at the same time.
+const MAX_PARALLEL_CLEARS: usize = 8;
 
 pub struct BotController {
     bot: Bot,
     nova_client: NovaClient,
     config: Config,
-    chat_states: Mutex<HashMap<i64, ChatState>>,
+    store: Arc<dyn ChatStateStore>,
+    rate_limiter: RateLimiter,
 }
 
 #[derive(Debug, Error)]
@@ -25,117 +58,1177 @@ pub enum BotError {
     Telegram(#[from] RequestError),
     #[error("nova gateway error: {0}")]
     Nova(#[from] NovaClientError),
+    #[error("file download error: {0}")]
+    Download(#[from] DownloadError),
+    #[error("chat state store error: {0}")]
+    Store(#[from] StoreError),
     #[error("I can only process text messages right now.")]
     MissingMessageText,
+    #[error("{0}")]
+    InvalidArgument(String),
+    #[error("only chat administrators can change this setting")]
+    AdminRequired,
+    #[error("only bot administrators can manage access")]
+    BotAdminRequired,
+    #[error("user or chat has no access to the bot")]
+    AccessDenied,
+    #[error("rate limit exceeded, retry in {0:?}")]
+    RateLimited(Duration),
+    #[error("{scope:?} {period:?} usage quota exhausted")]
+    QuotaExceeded { scope: UsageScope, period: QuotaPeriod },
+    #[error("{0}")]
+    Conversation(#[from] ConversationError),
 }
 
 impl BotController {
-    pub fn new(bot: Bot, nova_client: NovaClient, config: Config) -> Self {
+    pub fn new(bot: Bot, nova_client: NovaClient, store: Arc<dyn ChatStateStore>, config: Config) -> Self {
         Self {
             bot,
             nova_client,
+            rate_limiter: RateLimiter::new(config.rate_limits()),
             config,
-            chat_states: Mutex::new(HashMap::new()),
+            store,
         }
     }
 
     pub async fn handle_command(&self, message: &Message, command: BotCommand) -> Result<(), BotError> {
-        let chat_id = message.chat.id;
+        if !self.ensure_access(message).await? {
+            return Ok(());
+        }
+        METRICS.commands.with_label_values(&[command.name()]).inc();
 
         match command {
-            BotCommand::Help => self.send_help(chat_id).await,
-            BotCommand::Reset => self.reset_conversation(chat_id).await,
+            BotCommand::Help => self.send_help(message).await,
+            BotCommand::Reset => self.reset_conversation(message).await,
+            BotCommand::Mentions(argument) => self.set_mention_triggers(message, &argument).await,
+            BotCommand::Threads(argument) => self.set_thread_policy(message, &argument).await,
+            BotCommand::Model => self.send_model_menu(message).await,
+            BotCommand::Settings => self.send_settings_menu(message).await,
+            BotCommand::Persona(argument) => self.set_persona(message, &argument).await,
+            BotCommand::Usage => self.send_usage(message).await,
+            BotCommand::New(argument) => self.new_conversation(message, &argument).await,
+            BotCommand::Switch(argument) => self.switch_conversation(message, &argument).await,
+            BotCommand::List => self.send_conversation_list(message).await,
+            BotCommand::Rename(argument) => self.rename_conversation(message, &argument).await,
+            BotCommand::Delete(argument) => self.delete_conversation(message, &argument).await,
+            BotCommand::Grant(argument) => self.change_access(message, &argument, true).await,
+            BotCommand::Revoke(argument) => self.change_access(message, &argument, false).await,
+            BotCommand::Access(argument) => self.send_access_list(message, &argument).await,
             BotCommand::Chat => {
-                let text = helpers::extract_plain_text(message).ok_or(BotError::MissingMessageText)?;
+                let text = helpers::extract_plain_text(message).unwrap_or_default();
                 // Extract text after /chat command (handles both /chat and /chat@botname)
-                let prompt = if let Some(space_idx) = text.find(' ') {
-                    text[space_idx + 1..].to_string()
-                } else {
-                    // No space found, command has no arguments
-                    return Err(BotError::MissingMessageText);
+                let prompt = match text.find(char::is_whitespace) {
+                    Some(space_idx) => text[space_idx..].trim(),
+                    None => "",
                 };
-                
-                if prompt.trim().is_empty() {
+
+                if prompt.is_empty() && message.photo().is_none() {
                     return Err(BotError::MissingMessageText);
                 }
-                
-                self.forward_to_nova(chat_id, prompt.trim().to_string()).await
+
+                self.forward_prompt(message, prompt.to_string()).await
             }
         }
     }
 
-    pub async fn handle_text_message(&self, _message: &Message) -> Result<(), BotError> {
-        // Regular text messages are ignored - only /chat command is processed
-        Ok(())
+    pub async fn handle_text_message(&self, message: &Message, me: &Me) -> Result<(), BotError> {
+        // Unknown commands and messages without text or a photo are ignored
+        let text = helpers::extract_plain_text(message).unwrap_or_default();
+        if text.is_empty() && message.photo().is_none() {
+            return Ok(());
+        }
+        if text.trim_start().starts_with('/') {
+            // The command filter only looks at message text, so a photo
+            // captioned with a command ends up here
+            if message.photo().is_some()
+                && let Ok(command) = BotCommand::parse(&text, me.username())
+            {
+                return self.handle_command(message, command).await;
+            }
+            return Ok(());
+        }
+
+        let prompt = if message.chat.is_private() {
+            if self.config.private_chat_mode() == ConversationMode::Command {
+                return Ok(());
+            }
+            text
+        } else {
+            let mentioned = helpers::strip_bot_mention(message, me);
+            if self.config.group_chat_mode() == ConversationMode::Command {
+                let triggered = mentioned.is_some() || helpers::is_reply_to_bot(message, me);
+                if !triggered || !self.mention_triggers_enabled(message.chat.id).await? {
+                    return Ok(());
+                }
+            }
+            mentioned.unwrap_or(text)
+        };
+
+        if !self.ensure_access(message).await? {
+            return Ok(());
+        }
+        self.forward_prompt(message, prompt).await
+    }
+
+    pub async fn handle_callback(&self, query: &CallbackQuery) -> Result<(), BotError> {
+        let result = self.dispatch_callback(query).await;
+        if result.is_err() {
+            // Stop the button's loading spinner; the error itself is reported
+            // in the chat. The query may have been answered already, which
+            // Telegram rejects and is fine to ignore.
+            if let Err(err) = utils::answer_callback(&self.bot, query, None).await {
+                tracing::debug!(error = %err, "failed to answer callback query after an error");
+            }
+        }
+        result
+    }
+
+    async fn dispatch_callback(&self, query: &CallbackQuery) -> Result<(), BotError> {
+        let action = query.data.as_deref().and_then(CallbackAction::parse);
+        let (Some(message), Some(action)) = (&query.message, action) else {
+            // Buttons on messages too old to be delivered, or left over
+            // from an earlier version of the bot
+            utils::answer_callback(&self.bot, query, None).await?;
+            return Ok(());
+        };
+
+        let user_id = Some(query.from.id.0 as i64);
+        match self.access(user_id, message.chat.id).await? {
+            AccessDecision::Allowed => {}
+            AccessDecision::Blocked => {
+                tracing::info!(user_id, "ignoring blocked user or chat");
+                utils::answer_callback(&self.bot, query, None).await?;
+                return Ok(());
+            }
+            AccessDecision::NotAllowed => {
+                utils::answer_callback(&self.bot, query, Some("You don't have access to this bot.")).await?;
+                return Ok(());
+            }
+        }
+        METRICS.callbacks.with_label_values(&[action.name()]).inc();
+
+        match action {
+            CallbackAction::SetModel(model) => self.select_model(query, message, model).await,
+            CallbackAction::Setting(change) => self.change_setting(query, message, change).await,
+            CallbackAction::SetPersona(name) => self.select_persona(query, message, name).await,
+            CallbackAction::Answer(action) => self.rework_answer(query, message, action).await,
+        }
     }
 
-    pub async fn notify_error(&self, chat_id: ChatId, error: &BotError) -> Result<(), RequestError> {
+    /// Tells the user about `error`, replying to the message that caused it
+    /// when there is one. The reply joins that message's reply chain, so
+    /// answering it continues the same conversation.
+    pub async fn notify_error(
+        &self,
+        to: Destination,
+        cause: Option<&Message>,
+        error: &BotError,
+    ) -> Result<(), RequestError> {
         if matches!(error, BotError::Telegram(_)) {
             return Ok(());
         }
         let message = error.user_message();
         let fallback = "Something went wrong while handling your request. Please try again.";
         let text = message.unwrap_or_else(|| fallback.to_string());
-        utils::send_error(&self.bot, chat_id, text).await
+        let sent = utils::send_error(&self.bot, to, text, cause.map(|cause| cause.id)).await?;
+
+        if let Some(cause) = cause
+            && let Err(err) = self.record_replies(cause, vec![sent.id]).await
+        {
+            tracing::warn!(error = %err, "failed to record reply chain");
+        }
+        Ok(())
     }
 
-    async fn send_help(&self, chat_id: ChatId) -> Result<(), BotError> {
-        let help_text = helpers::format_help_text();
-        utils::send_text(&self.bot, chat_id, help_text).await?;
+    async fn forward_prompt(&self, message: &Message, text: String) -> Result<(), BotError> {
+        let prompt = text.trim();
+        if !prompt.is_empty() {
+            self.forward_to_nova(message, prompt.to_string(), Delivery::Reply).await
+        } else if message.photo().is_some() {
+            self.forward_to_nova(message, helpers::DEFAULT_IMAGE_PROMPT.to_string(), Delivery::Reply)
+                .await
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Resolves the largest size of an attached photo into a URL the
+    /// gateway can fetch or decode.
+    async fn resolve_image_urls(&self, message: &Message) -> Result<Vec<String>, BotError> {
+        let Some(photo) = message.photo().and_then(|sizes| sizes.last()) else {
+            return Ok(Vec::new());
+        };
+
+        let file = self.bot.get_file(&photo.file.id).await?;
+        let url = match self.config.image_mode() {
+            ImageMode::DataUrl => utils::file_data_url(&self.bot, &file, "image/jpeg").await?,
+            ImageMode::FileUrl => utils::file_url(&self.bot, &file),
+        };
+        Ok(vec![url])
+    }
+
+    async fn mention_triggers_enabled(&self, chat_id: ChatId) -> Result<bool, BotError> {
+        let state = self.store.load(chat_id.0).await?;
+        Ok(state.mention_triggers.unwrap_or_else(|| self.config.group_triggers()))
+    }
+
+    async fn set_mention_triggers(&self, message: &Message, argument: &str) -> Result<(), BotError> {
+        let chat_id = message.chat.id;
+        let to = Destination::of(message);
+        if message.chat.is_private() {
+            utils::send_reply(&self.bot, to, "Mention triggers only apply to group chats.", message.id).await?;
+            return Ok(());
+        }
+
+        let enabled = match argument.trim().to_lowercase().as_str() {
+            "" => {
+                let status = if self.mention_triggers_enabled(chat_id).await? { "on" } else { "off" };
+                let text = format!("Answering @mentions and replies is {status} in this chat.");
+                utils::send_reply(&self.bot, to, text, message.id).await?;
+                return Ok(());
+            }
+            "on" => true,
+            "off" => false,
+            _ => return Err(BotError::InvalidArgument("Usage: /mentions on or /mentions off".to_string())),
+        };
+
+        if !self.is_chat_admin(&message.chat, message.from().map(|user| user.id)).await? {
+            return Err(BotError::AdminRequired);
+        }
+
+        self.store
+            .update(chat_id.0, Box::new(move |state| state.mention_triggers = Some(enabled)))
+            .await?;
+
+        let text = if enabled {
+            "I'll answer when I'm @mentioned or when someone replies to me."
+        } else {
+            "I'll only answer /chat commands in this chat."
+        };
+        utils::send_reply(&self.bot, to, text, message.id).await?;
         Ok(())
     }
 
-    async fn reset_conversation(&self, chat_id: ChatId) -> Result<(), BotError> {
-        let ref_id = self.ensure_ref_id(chat_id).await;
-        self.nova_client
-            .clear_history(Some(ref_id.clone()))
+    async fn set_thread_policy(&self, message: &Message, argument: &str) -> Result<(), BotError> {
+        let chat_id = message.chat.id;
+        let to = Destination::of(message);
+        if message.chat.is_private() {
+            utils::send_reply(&self.bot, to, "Conversation threads only apply to group chats.", message.id).await?;
+            return Ok(());
+        }
+
+        let argument = argument.trim();
+        if argument.is_empty() {
+            let state = self.store.load(chat_id.0).await?;
+            let policy = self.thread_policy(&message.chat, &state);
+            let text = format!("{}\n\nUsage: /threads shared, /threads user or /threads reply", helpers::format_thread_policy(policy));
+            utils::send_reply(&self.bot, to, text, message.id).await?;
+            return Ok(());
+        }
+        let Some(policy) = ThreadPolicy::parse(argument) else {
+            let usage = "Usage: /threads shared, /threads user or /threads reply".to_string();
+            return Err(BotError::InvalidArgument(usage));
+        };
+
+        if !self.is_chat_admin(&message.chat, message.from().map(|user| user.id)).await? {
+            return Err(BotError::AdminRequired);
+        }
+
+        self.store
+            .update(chat_id.0, Box::new(move |state| state.thread_policy = Some(policy)))
+            .await?;
+        utils::send_reply(&self.bot, to, helpers::format_thread_policy(policy), message.id).await?;
+        Ok(())
+    }
+
+    /// Private chats have a single participant, so they always share one
+    /// conversation.
+    fn thread_policy(&self, chat: &Chat, state: &ChatState) -> ThreadPolicy {
+        if chat.is_private() {
+            return ThreadPolicy::Shared;
+        }
+        state.thread_policy.unwrap_or_else(|| self.config.group_thread_policy())
+    }
+
+    async fn send_model_menu(&self, message: &Message) -> Result<(), BotError> {
+        let to = Destination::of(message);
+        let state = self.store.load(message.chat.id.0).await?;
+        let current = self.chat_model(&state);
+        let keyboard = helpers::model_keyboard(self.config.nova_models(), current);
+        let text = helpers::format_model_menu(current);
+        utils::send_menu(&self.bot, to, text, keyboard, message.id).await?;
+        Ok(())
+    }
+
+    async fn select_model(&self, query: &CallbackQuery, message: &Message, model: String) -> Result<(), BotError> {
+        if !self.is_chat_admin(&message.chat, Some(query.from.id)).await? {
+            utils::answer_callback(&self.bot, query, Some("Only chat administrators can change the model.")).await?;
+            return Ok(());
+        }
+        if !self.config.nova_models().contains(&model) {
+            utils::answer_callback(&self.bot, query, Some("That model is no longer available.")).await?;
+            return self.refresh_model_menu(message).await;
+        }
+
+        let selected = model.clone();
+        self.store
+            .update(message.chat.id.0, Box::new(move |state| state.model = Some(selected)))
             .await?;
 
+        utils::answer_callback(&self.bot, query, Some(&format!("Model set to {model}."))).await?;
+        self.refresh_model_menu(message).await
+    }
+
+    async fn refresh_model_menu(&self, message: &Message) -> Result<(), BotError> {
+        let state = self.store.load(message.chat.id.0).await?;
+        let current = self.chat_model(&state);
+        let keyboard = helpers::model_keyboard(self.config.nova_models(), current);
+        let text = helpers::format_model_menu(current);
+        utils::edit_menu(&self.bot, message, text, Some(keyboard)).await?;
+        Ok(())
+    }
+
+    async fn send_settings_menu(&self, message: &Message) -> Result<(), BotError> {
+        let to = Destination::of(message);
+        let state = self.store.load(message.chat.id.0).await?;
+        let text = helpers::format_settings_menu(&state.settings, &self.config);
+        let keyboard = helpers::settings_keyboard(&state.settings, &self.config);
+        utils::send_menu(&self.bot, to, text, keyboard, message.id).await?;
+        Ok(())
+    }
+
+    async fn change_setting(&self, query: &CallbackQuery, message: &Message, change: SettingChange) -> Result<(), BotError> {
+        if !self.is_chat_admin(&message.chat, Some(query.from.id)).await? {
+            utils::answer_callback(&self.bot, query, Some("Only chat administrators can change settings.")).await?;
+            return Ok(());
+        }
+        if let SettingChange::MaxTokens(tokens) = change
+            && !self.config.token_bounds().contains(tokens)
+        {
+            utils::answer_callback(&self.bot, query, Some("That token cap is outside the allowed range.")).await?;
+            return self.refresh_settings_menu(message).await;
+        }
+
+        self.store
+            .update(message.chat.id.0, Box::new(move |state| state.settings.apply(change)))
+            .await?;
+
+        utils::answer_callback(&self.bot, query, Some("Settings updated.")).await?;
+        self.refresh_settings_menu(message).await
+    }
+
+    async fn refresh_settings_menu(&self, message: &Message) -> Result<(), BotError> {
+        let state = self.store.load(message.chat.id.0).await?;
+        let text = helpers::format_settings_menu(&state.settings, &self.config);
+        let keyboard = helpers::settings_keyboard(&state.settings, &self.config);
+        utils::edit_menu(&self.bot, message, text, Some(keyboard)).await?;
+        Ok(())
+    }
+
+    async fn set_persona(&self, message: &Message, argument: &str) -> Result<(), BotError> {
+        let chat_id = message.chat.id;
+        let to = Destination::of(message);
+        let argument = argument.trim();
+        if argument.is_empty() {
+            return self.send_persona_menu(message).await;
+        }
+
+        let persona = if argument.eq_ignore_ascii_case("clear") {
+            None
+        } else if self.config.personas().contains_key(argument) {
+            Some(Persona::Preset {
+                name: argument.to_string(),
+            })
+        } else if argument.chars().count() > MAX_PERSONA_PROMPT_CHARS {
+            let usage = format!("Personas can be at most {MAX_PERSONA_PROMPT_CHARS} characters long.");
+            return Err(BotError::InvalidArgument(usage));
+        } else {
+            Some(Persona::Custom {
+                prompt: argument.to_string(),
+            })
+        };
+
+        if !self.is_chat_admin(&message.chat, message.from().map(|user| user.id)).await? {
+            return Err(BotError::AdminRequired);
+        }
+
+        let text = match &persona {
+            None => "Persona cleared.".to_string(),
+            Some(Persona::Preset { name }) => format!("Persona set to {name}."),
+            Some(Persona::Custom { .. }) => "Custom persona set.".to_string(),
+        };
+        self.store
+            .update(chat_id.0, Box::new(move |state| state.persona = persona))
+            .await?;
+        utils::send_reply(&self.bot, to, text, message.id).await?;
+        Ok(())
+    }
+
+    async fn send_persona_menu(&self, message: &Message) -> Result<(), BotError> {
+        let to = Destination::of(message);
+        let state = self.store.load(message.chat.id.0).await?;
+        let text = helpers::format_persona(state.persona.as_ref(), self.config.personas());
+        let keyboard = helpers::persona_keyboard(state.persona.as_ref(), self.config.personas());
+        match keyboard {
+            Some(keyboard) => utils::send_menu(&self.bot, to, text, keyboard, message.id).await?,
+            None => {
+                utils::send_reply(&self.bot, to, text, message.id).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn select_persona(&self, query: &CallbackQuery, message: &Message, name: Option<String>) -> Result<(), BotError> {
+        if !self.is_chat_admin(&message.chat, Some(query.from.id)).await? {
+            utils::answer_callback(&self.bot, query, Some("Only chat administrators can change the persona.")).await?;
+            return Ok(());
+        }
+        if let Some(name) = &name
+            && !self.config.personas().contains_key(name)
         {
-            let mut states = self.chat_states.lock().await;
-            if let Some(state) = states.get_mut(&chat_id.0) {
-                state.ref_id = Some(ref_id);
+            utils::answer_callback(&self.bot, query, Some("That persona is no longer available.")).await?;
+            return self.refresh_persona_menu(message).await;
+        }
+
+        let notice = match &name {
+            Some(name) => format!("Persona set to {name}."),
+            None => "Persona cleared.".to_string(),
+        };
+        let persona = name.map(|name| Persona::Preset { name });
+        self.store
+            .update(message.chat.id.0, Box::new(move |state| state.persona = persona))
+            .await?;
+
+        utils::answer_callback(&self.bot, query, Some(&notice)).await?;
+        self.refresh_persona_menu(message).await
+    }
+
+    async fn refresh_persona_menu(&self, message: &Message) -> Result<(), BotError> {
+        let state = self.store.load(message.chat.id.0).await?;
+        let text = helpers::format_persona(state.persona.as_ref(), self.config.personas());
+        let keyboard = helpers::persona_keyboard(state.persona.as_ref(), self.config.personas());
+        utils::edit_menu(&self.bot, message, text, keyboard).await?;
+        Ok(())
+    }
+
+    /// The system prompt for the chat's persona, if one is set and its
+    /// preset still exists.
+    fn persona_prompt<'a>(&'a self, state: &'a ChatState) -> Option<&'a str> {
+        match state.persona.as_ref()? {
+            Persona::Preset { name } => self.config.personas().get(name).map(String::as_str),
+            Persona::Custom { prompt } => Some(prompt),
+        }
+    }
+
+    /// The chat's chosen model, or the default when none was chosen or the
+    /// choice has since been removed from the allowlist.
+    fn chat_model<'a>(&'a self, state: &'a ChatState) -> &'a str {
+        state
+            .model
+            .as_deref()
+            .filter(|model| self.config.nova_models().iter().any(|allowed| allowed == model))
+            .unwrap_or_else(|| self.config.nova_model())
+    }
+
+    /// Whether the message should be handled. Blocked users and chats are
+    /// ignored without a reply, so the bot doesn't reveal itself to them;
+    /// anyone else without access is told so.
+    async fn ensure_access(&self, message: &Message) -> Result<bool, BotError> {
+        let user_id = message.from().map(|user| user.id.0 as i64);
+        match self.access(user_id, message.chat.id).await? {
+            AccessDecision::Allowed => Ok(true),
+            AccessDecision::Blocked => {
+                tracing::info!(user_id, "ignoring blocked user or chat");
+                Ok(false)
+            }
+            AccessDecision::NotAllowed => {
+                tracing::info!(user_id, "access denied");
+                Err(BotError::AccessDenied)
             }
         }
+    }
+
+    async fn access(&self, user_id: Option<i64>, chat_id: ChatId) -> Result<AccessDecision, BotError> {
+        let access = self.store.load_access().await?;
+        Ok(access.permits(self.config.access(), user_id, chat_id.0))
+    }
+
+    fn is_bot_admin(&self, user_id: Option<UserId>) -> bool {
+        user_id.is_some_and(|user_id| self.config.access().admins.contains(&(user_id.0 as i64)))
+    }
+
+    /// Handles `/grant` and `/revoke`. The target is the ID given as the
+    /// argument, or the author of the message being replied to.
+    async fn change_access(&self, message: &Message, argument: &str, grant: bool) -> Result<(), BotError> {
+        if !self.is_bot_admin(message.from().map(|user| user.id)) {
+            return Err(BotError::BotAdminRequired);
+        }
 
-        utils::send_text(&self.bot, chat_id, "Conversation context cleared.").await?;
+        let command = if grant { "/grant" } else { "/revoke" };
+        let replied_author = helpers::explicit_reply(message).and_then(|reply| reply.from());
+        let target = match argument.trim() {
+            "" => replied_author.map(|user| user.id.0 as i64),
+            id => id.parse::<i64>().ok(),
+        };
+        let Some(target) = target else {
+            let usage = format!("Usage: {command} <user or chat id>, or reply to a user's message with {command}");
+            return Err(BotError::InvalidArgument(usage));
+        };
+        if self.config.access().admins.contains(&target) {
+            return Err(BotError::InvalidArgument("Bot administrators always have access.".to_string()));
+        }
+
+        let access = self
+            .store
+            .update_access(Box::new(move |access| {
+                if grant {
+                    access.grant(target);
+                } else {
+                    access.revoke(target);
+                }
+            }))
+            .await?;
+
+        tracing::info!(target, grant, "access changed");
+        let kind = if target < 0 { "Chat" } else { "User" };
+        let text = if grant && !access.is_restricted(self.config.access()) {
+            format!(
+                "{kind} {target} is now allowed. The bot is still open to everyone; use /access restrict to limit it \
+                 to allowed users and chats."
+            )
+        } else if grant {
+            format!("{kind} {target} can now use the bot.")
+        } else {
+            format!("{kind} {target} can no longer use the bot.")
+        };
+        utils::send_reply(&self.bot, Destination::of(message), text, message.id).await?;
         Ok(())
     }
 
-    async fn forward_to_nova(&self, chat_id: ChatId, text: String) -> Result<(), BotError> {
-        let _typing_indicator = TypingIndicator::start(self.bot.clone(), chat_id);
-        let ref_id = self.ensure_ref_id(chat_id).await;
-        let request = nova_helpers::create_request(
+    /// Handles `/access`, which lists the access rules, and `/access restrict`
+    /// and `/access open`, which limit the bot to allowed users and chats and
+    /// lift that limit again.
+    async fn send_access_list(&self, message: &Message, argument: &str) -> Result<(), BotError> {
+        if !self.is_bot_admin(message.from().map(|user| user.id)) {
+            return Err(BotError::BotAdminRequired);
+        }
+        let access = match argument.trim() {
+            "" => self.store.load_access().await?,
+            "restrict" => {
+                let access = self.store.update_access(Box::new(|access| access.restrict())).await?;
+                tracing::info!("access restricted");
+                access
+            }
+            "open" => {
+                let access = self.store.update_access(Box::new(|access| access.open())).await?;
+                tracing::info!("access restriction lifted");
+                access
+            }
+            _ => {
+                let usage = "Usage: /access, /access restrict or /access open";
+                return Err(BotError::InvalidArgument(usage.to_string()));
+            }
+        };
+        let text = helpers::format_access_list(self.config.access(), &access);
+        utils::send_reply(&self.bot, Destination::of(message), text, message.id).await?;
+        Ok(())
+    }
+
+    async fn new_conversation(&self, message: &Message, argument: &str) -> Result<(), BotError> {
+        if !self.is_chat_admin(&message.chat, message.from().map(|user| user.id)).await? {
+            return Err(BotError::AdminRequired);
+        }
+
+        let chat_id = message.chat.id;
+        let default_ref = self.ensure_state(chat_id).await?.ref_id.unwrap_or_else(|| chat_id.0.to_string());
+        // A fresh ref_id, so a deleted conversation's name can be reused
+        // without picking up its history
+        let ref_id = format!("{default_ref}:conversation:{}", Utc::now().timestamp_millis());
+        let name = self
+            .update_conversations(chat_id, |conversations| {
+                let name = match argument.trim() {
+                    "" => conversations.next_name(),
+                    name => name.to_string(),
+                };
+                conversations.create(&name, ref_id)?;
+                Ok(name)
+            })
+            .await?;
+
+        let text = format!("Started a new conversation called {name}. Use /switch {DEFAULT_CONVERSATION} to go back.");
+        utils::send_reply(&self.bot, Destination::of(message), text, message.id).await?;
+        Ok(())
+    }
+
+    async fn switch_conversation(&self, message: &Message, argument: &str) -> Result<(), BotError> {
+        let name = argument.trim();
+        if name.is_empty() {
+            return self.send_conversation_list(message).await;
+        }
+        if !self.is_chat_admin(&message.chat, message.from().map(|user| user.id)).await? {
+            return Err(BotError::AdminRequired);
+        }
+
+        self.update_conversations(message.chat.id, |conversations| conversations.switch(name))
+            .await?;
+        let text = format!("Switched to the conversation {name}.");
+        utils::send_reply(&self.bot, Destination::of(message), text, message.id).await?;
+        Ok(())
+    }
+
+    async fn send_conversation_list(&self, message: &Message) -> Result<(), BotError> {
+        let state = self.store.load(message.chat.id.0).await?;
+        let text = helpers::format_conversation_list(&state.conversations);
+        utils::send_reply(&self.bot, Destination::of(message), text, message.id).await?;
+        Ok(())
+    }
+
+    async fn rename_conversation(&self, message: &Message, argument: &str) -> Result<(), BotError> {
+        let words: Vec<_> = argument.split_whitespace().collect();
+        let [name, new_name] = words[..] else {
+            return Err(BotError::InvalidArgument("Usage: /rename <name> <new name>".to_string()));
+        };
+        if !self.is_chat_admin(&message.chat, message.from().map(|user| user.id)).await? {
+            return Err(BotError::AdminRequired);
+        }
+
+        self.update_conversations(message.chat.id, |conversations| conversations.rename(name, new_name))
+            .await?;
+        let text = format!("Renamed the conversation {name} to {new_name}.");
+        utils::send_reply(&self.bot, Destination::of(message), text, message.id).await?;
+        Ok(())
+    }
+
+    /// Deletes a named conversation and clears its history on the gateway,
+    /// including the histories kept per topic, user or reply thread.
+    async fn delete_conversation(&self, message: &Message, argument: &str) -> Result<(), BotError> {
+        let name = argument.trim();
+        if name.is_empty() {
+            return Err(BotError::InvalidArgument("Usage: /delete <name>".to_string()));
+        }
+        if !self.is_chat_admin(&message.chat, message.from().map(|user| user.id)).await? {
+            return Err(BotError::AdminRequired);
+        }
+
+        // The history goes first, so a failure leaves the conversation in
+        // place to try again
+        let chat_id = message.chat.id;
+        let state = self.store.load(chat_id.0).await?;
+        let conversation = state.conversations.get(name)?;
+        self.clear_histories(conversation.refs().cloned().collect()).await?;
+
+        let was_active = self
+            .update_conversations(chat_id, |conversations| {
+                let was_active = conversations.active_name() == name;
+                conversations.remove(name)?;
+                Ok(was_active)
+            })
+            .await?;
+        let text = if was_active {
+            format!("Deleted the conversation {name}. Switched back to {DEFAULT_CONVERSATION}.")
+        } else {
+            format!("Deleted the conversation {name}.")
+        };
+        utils::send_reply(&self.bot, Destination::of(message), text, message.id).await?;
+        Ok(())
+    }
+
+    /// Clears every history in `ref_ids`, a few at a time. A history the
+    /// gateway doesn't know counts as cleared, so retrying after a partial
+    /// failure only has to deal with what is left.
+    async fn clear_histories(&self, ref_ids: Vec<String>) -> Result<(), NovaClientError> {
+        let results: Vec<_> = stream::iter(ref_ids)
+            .map(|ref_id| self.nova_client.clear_history(Some(ref_id)))
+            .buffer_unordered(MAX_PARALLEL_CLEARS)
+            .collect()
+            .await;
+
+        let mut failures = results.into_iter().filter_map(|result| match result {
+            Err(err) if err.status() != Some(404) => Some(err),
+            _ => None,
+        });
+        match failures.next() {
+            Some(err) => {
+                tracing::warn!(error = %err, remaining = failures.count() + 1, "failed to clear conversation histories");
+                Err(err)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Applies `change` to the chat's conversations within a single store
+    /// update, so concurrent changes to the chat state aren't lost.
+    async fn update_conversations<T: Send>(
+        &self,
+        chat_id: ChatId,
+        change: impl FnOnce(&mut Conversations) -> Result<T, ConversationError> + Send,
+    ) -> Result<T, BotError> {
+        let mut outcome = None;
+        self.store
+            .update(chat_id.0, Box::new(|state| outcome = Some(change(&mut state.conversations))))
+            .await?;
+        Ok(outcome.expect("the store applies every update")?)
+    }
+
+    /// Bot administrators count as admins of every chat.
+    async fn is_chat_admin(&self, chat: &Chat, user_id: Option<UserId>) -> Result<bool, BotError> {
+        if chat.is_private() || self.is_bot_admin(user_id) {
+            return Ok(true);
+        }
+        let Some(user_id) = user_id else {
+            return Ok(false);
+        };
+        let member = self.bot.get_chat_member(chat.id, user_id).await?;
+        Ok(member.is_privileged())
+    }
+
+    async fn send_help(&self, message: &Message) -> Result<(), BotError> {
+        let to = Destination::of(message);
+        let help_text = helpers::format_help_text();
+        utils::send_reply(&self.bot, to, help_text, message.id).await?;
+        Ok(())
+    }
+
+    /// Clears the conversation the caller is part of: the whole chat's, only
+    /// their own, or the reply thread `/reset` answers, depending on the
+    /// chat's thread policy.
+    async fn reset_conversation(&self, message: &Message) -> Result<(), BotError> {
+        let chat_id = message.chat.id;
+        let to = Destination::of(message);
+        let state = self.ensure_state(chat_id).await?;
+        let default_ref = state.ref_id.clone().unwrap_or_else(|| chat_id.0.to_string());
+        let chat_ref = state.conversations.active_ref(&default_ref);
+        let policy = self.thread_policy(&message.chat, &state);
+
+        let text = match policy {
+            ThreadPolicy::Shared => "Conversation context cleared.",
+            ThreadPolicy::PerUser => "Your conversation context in this chat was cleared.",
+            ThreadPolicy::PerReply if helpers::explicit_reply(message).is_none() => {
+                let usage = "Reply to a message of the conversation you want to clear with /reset.".to_string();
+                return Err(BotError::InvalidArgument(usage));
+            }
+            ThreadPolicy::PerReply => "The context of this thread was cleared.",
+        };
+
+        let ref_id = helpers::conversation_ref(chat_ref, policy, message, &state.threads);
+        self.nova_client
+            .clear_history(Some(ref_id))
+            .await?;
+
+        utils::send_reply(&self.bot, to, text, message.id).await?;
+        Ok(())
+    }
+
+    /// Handles the buttons under an answer by asking again on behalf of the
+    /// message the answer replies to.
+    async fn rework_answer(&self, query: &CallbackQuery, answer: &Message, action: AnswerAction) -> Result<(), BotError> {
+        let state = self.store.load(answer.chat.id.0).await?;
+        let (Some(prompt_message), Some(answered)) = (helpers::explicit_reply(answer), state.answers.get(answer.id.0))
+        else {
+            utils::answer_callback(&self.bot, query, Some("That answer can no longer be changed.")).await?;
+            return Ok(());
+        };
+        // The prompt is charged to its author, so only they may replay it
+        let author = prompt_message.from().map(|user| user.id);
+        if author != Some(query.from.id) && !self.is_bot_admin(Some(query.from.id)) {
+            utils::answer_callback(&self.bot, query, Some("Only the person who asked can do that.")).await?;
+            return Ok(());
+        }
+        utils::answer_callback(&self.bot, query, None).await?;
+
+        let previous = answered.message_ids.iter().copied().map(MessageId).collect();
+        let (prompt, delivery) = match action {
+            AnswerAction::Regenerate => (answered.prompt.clone(), Delivery::Replace(previous)),
+            AnswerAction::Continue => (helpers::CONTINUE_PROMPT.to_string(), Delivery::Reply),
+            AnswerAction::Shorter => (helpers::SHORTER_PROMPT.to_string(), Delivery::Replace(previous)),
+        };
+        self.forward_to_nova(prompt_message, prompt, delivery).await
+    }
+
+    async fn forward_to_nova(&self, message: &Message, text: String, delivery: Delivery) -> Result<(), BotError> {
+        let chat_id = message.chat.id;
+        let to = Destination::of(message);
+        let user_id = message.from().map(|user| user.id.0 as i64);
+        if !self.is_bot_admin(message.from().map(|user| user.id)) {
+            self.check_quotas(user_id, chat_id).await?;
+            self.rate_limiter.acquire(user_id, chat_id.0).map_err(BotError::RateLimited)?;
+        }
+        let _typing_indicator = TypingIndicator::start(self.bot.clone(), to);
+        let state = self.ensure_state(chat_id).await?;
+        let default_ref = state.ref_id.clone().unwrap_or_else(|| chat_id.0.to_string());
+        let chat_ref = state.conversations.active_ref(&default_ref);
+        let policy = self.thread_policy(&message.chat, &state);
+        let ref_id = helpers::conversation_ref(chat_ref, policy, message, &state.threads);
+        let image_urls = self.resolve_image_urls(message).await?;
+        let prompt = text.clone();
+        let mut request = nova_helpers::create_request(
             Some(ref_id.clone()),
             text,
-            self.config.nova_model(),
-            self.config.nova_verbosity(),
-            self.config.nova_max_tokens(),
-            self.config.reasoning(),
+            image_urls,
+            self.chat_model(&state),
+            state.settings.verbosity(self.config.nova_verbosity()),
+            state.settings.max_tokens(self.config.nova_max_tokens(), self.config.token_bounds()),
+            &state.settings.reasoning(self.config.reasoning()),
         );
+        if let Some(instructions) = self.persona_prompt(&state) {
+            nova_helpers::apply_instructions(&mut request, instructions, self.config.instruction_mode());
+        }
+
+        let input_tokens = nova_helpers::estimate_tokens(&request.input)
+            + request.instructions.as_deref().map_or(0, nova_helpers::estimate_tokens);
+
+        let (response, draft) = match &delivery {
+            Delivery::Reply if self.config.nova_streaming() => self.stream_to_chat(message, request).await?,
+            _ => (self.nova_client.send_prompt(request).await?, None),
+        };
+
+        // Charged before the answer is posted, so a failure to post it can't
+        // be used to get around the quotas
+        let tokens = response.usage.and_then(|usage| usage.total()).unwrap_or_else(|| {
+            input_tokens + response.text.as_deref().map_or(0, nova_helpers::estimate_tokens)
+        });
+        self.record_usage(user_id, chat_id, &ref_id, tokens).await;
 
-        let response = self.nova_client.send_prompt(request).await?;
         let reply = helpers::format_nova_response(&response);
-        utils::send_text(&self.bot, chat_id, reply).await?;
+        let keyboard = self.config.answer_buttons().then(helpers::answer_keyboard);
+        let replies = match (delivery, draft) {
+            (Delivery::Replace(previous), _) => self.replace_answer(message, &reply, previous, keyboard.clone()).await?,
+            (Delivery::Reply, Some(draft)) => self.finish_draft(message, &draft, &reply, keyboard.clone()).await?,
+            (Delivery::Reply, None) => {
+                let sent = utils::send_long_reply(&self.bot, to, &reply, message.id, keyboard.clone()).await?;
+                sent.iter().map(|reply| reply.id).collect()
+            }
+        };
+
+        // The answer is out, so failing to remember it only costs the
+        // buttons or the reply thread and is not worth an error notice
+        if keyboard.is_some()
+            && let Err(err) = self.remember_answer(chat_id, prompt, &replies).await
+        {
+            tracing::warn!(error = %err, "failed to remember answered prompt");
+        }
+        if let Err(err) = self.record_replies(message, replies).await {
+            tracing::warn!(error = %err, "failed to record reply chain");
+        }
         Ok(())
     }
 
-    async fn ensure_ref_id(&self, chat_id: ChatId) -> String {
-        let mut states = self.chat_states.lock().await;
-        let state = states.entry(chat_id.0).or_insert_with(ChatState::default);
-        state
-            .ref_id
-            .get_or_insert_with(|| chat_id.0.to_string())
-            .clone()
+    /// Sends the answer to `message` in place of an earlier one: the earlier
+    /// messages are edited in order, any left over are deleted, and parts
+    /// that don't fit are sent as new replies. `keyboard` goes on the last
+    /// part.
+    async fn replace_answer(
+        &self,
+        message: &Message,
+        reply: &str,
+        previous: Vec<MessageId>,
+        keyboard: Option<InlineKeyboardMarkup>,
+    ) -> Result<Vec<MessageId>, BotError> {
+        let chat_id = message.chat.id;
+        let parts = utils::split_markdown(reply);
+        let last = parts.len().saturating_sub(1);
+        let mut previous = previous.into_iter();
+        let mut sent = Vec::new();
+        for (index, part) in parts.into_iter().enumerate() {
+            let keyboard = keyboard.clone().filter(|_| index == last);
+            match previous.next() {
+                Some(message_id) => {
+                    utils::edit_formatted(&self.bot, chat_id, message_id, &part, keyboard).await?;
+                    sent.push(message_id);
+                }
+                None => {
+                    let to = Destination::of(message);
+                    sent.push(utils::send_formatted_reply(&self.bot, to, &part, message.id, keyboard).await?.id);
+                }
+            }
+        }
+        for message_id in previous {
+            // Bots can only delete their messages for 48 hours; an old
+            // leftover part is not worth failing the answer for
+            if let Err(err) = utils::delete_message(&self.bot, chat_id, message_id).await {
+                tracing::warn!(error = %err, message_id = message_id.0, "failed to delete replaced answer part");
+            }
+        }
+        Ok(sent)
+    }
+
+    /// Remembers the prompt the buttons under the last of `replies` replay.
+    async fn remember_answer(&self, chat_id: ChatId, prompt: String, replies: &[MessageId]) -> Result<(), BotError> {
+        let Some(&last) = replies.last() else {
+            return Ok(());
+        };
+        let answer = AnsweredPrompt {
+            prompt,
+            message_ids: replies.iter().map(|id| id.0).collect(),
+        };
+        self.store
+            .update(chat_id.0, Box::new(move |state| state.answers.record(last.0, answer)))
+            .await?;
+        Ok(())
+    }
+
+    /// Adds `message` and the bot's `replies` to it to the reply chain
+    /// `message` belongs to, in chats where every reply thread is its own
+    /// conversation.
+    async fn record_replies(&self, message: &Message, replies: Vec<MessageId>) -> Result<(), BotError> {
+        let state = self.store.load(message.chat.id.0).await?;
+        if self.thread_policy(&message.chat, &state) != ThreadPolicy::PerReply {
+            return Ok(());
+        }
+        let root = helpers::reply_root(message, &state.threads);
+        let message_ids: Vec<_> = std::iter::once(message.id).chain(replies).map(|id| id.0).collect();
+        self.store
+            .update(message.chat.id.0, Box::new(move |state| state.threads.record(message_ids, root)))
+            .await?;
+        Ok(())
+    }
+
+    /// Fails when the user or the chat has used up a daily or monthly
+    /// allowance.
+    async fn check_quotas(&self, user_id: Option<i64>, chat_id: ChatId) -> Result<(), BotError> {
+        let quotas = self.config.quotas();
+        let today = Utc::now().date_naive();
+
+        if let Some(user_id) = user_id {
+            let user = self.store.load_user(user_id).await?;
+            if let Some(period) = user.usage.exhausted(&quotas.per_user, today) {
+                return Err(BotError::QuotaExceeded {
+                    scope: UsageScope::User,
+                    period,
+                });
+            }
+        }
+
+        let chat = self.store.load(chat_id.0).await?;
+        if let Some(period) = chat.usage.exhausted(&quotas.per_chat, today) {
+            return Err(BotError::QuotaExceeded {
+                scope: UsageScope::Chat,
+                period,
+            });
+        }
+        Ok(())
+    }
+
+    /// Counts a prompt against the user's and the chat's allowance, and as
+    /// use of the conversation `ref_id` belongs to. The gateway has already
+    /// answered, so a store failure is logged rather than costing the user
+    /// the answer.
+    async fn record_usage(&self, user_id: Option<i64>, chat_id: ChatId, ref_id: &str, tokens: u64) {
+        if let Err(err) = self.try_record_usage(user_id, chat_id, ref_id, tokens).await {
+            tracing::error!(error = %err, tokens, "failed to record usage");
+        }
+    }
+
+    async fn try_record_usage(
+        &self,
+        user_id: Option<i64>,
+        chat_id: ChatId,
+        ref_id: &str,
+        tokens: u64,
+    ) -> Result<(), BotError> {
+        let now = Utc::now();
+        let today = now.date_naive();
+        if let Some(user_id) = user_id {
+            self.store
+                .update_user(user_id, Box::new(move |user| user.usage.record(today, tokens)))
+                .await?;
+        }
+        self.store
+            .update(
+                chat_id.0,
+                Box::new(move |state| {
+                    state.usage.record(today, tokens);
+                    state.conversations.touch(now, ref_id);
+                }),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn send_usage(&self, message: &Message) -> Result<(), BotError> {
+        let quotas = self.config.quotas();
+        let user = match message.from() {
+            Some(user) => Some(self.store.load_user(user.id.0 as i64).await?),
+            None => None,
+        };
+        let chat = self.store.load(message.chat.id.0).await?;
+
+        let text = helpers::format_usage(
+            user.as_ref().map(|user| (&user.usage, &quotas.per_user)),
+            (&chat.usage, &quotas.per_chat),
+            Utc::now(),
+        );
+        utils::send_reply(&self.bot, Destination::of(message), text, message.id).await?;
+        Ok(())
+    }
+
+    /// Streams the answer into a draft message that is edited at most once
+    /// per `stream_edit_interval_ms` to stay clear of Telegram's edit limits.
+    /// Falls back to a regular request when the gateway refuses to stream.
+    /// Returns the complete response and the draft, if one was sent.
+    async fn stream_to_chat(
+        &self,
+        message: &Message,
+        request: NovaRequest,
+    ) -> Result<(NovaResponse, Option<Message>), BotError> {
+        let chat_id = message.chat.id;
+        let to = Destination::of(message);
+        let (delta_tx, mut delta_rx) = mpsc::unbounded_channel::<String>();
+        let edit_interval = Duration::from_millis(self.config.stream_edit_interval_ms());
+
+        // Previews are best-effort: a failed send or edit (typically flood
+        // control) only delays the next one, the final answer is sent anyway
+        let render = async {
+            let mut draft: Option<Message> = None;
+            let mut text = String::new();
+            let mut next_edit = Instant::now();
+
+            while let Some(delta) = delta_rx.recv().await {
+                text.push_str(&delta);
+                if Instant::now() < next_edit {
+                    continue;
+                }
+
+                let preview = helpers::format_stream_preview(&text);
+                let result = match &draft {
+                    None => match utils::send_draft(&self.bot, to, preview, message.id).await {
+                        Ok(sent) => {
+                            draft = Some(sent);
+                            Ok(())
+                        }
+                        Err(err) => Err(err),
+                    },
+                    Some(draft) => utils::edit_text(&self.bot, chat_id, draft.id, preview, None).await,
+                };
+                next_edit = Instant::now() + edit_interval;
+                if let Err(err) = result {
+                    tracing::warn!(error = %err, "failed to update streamed preview");
+                    if let RequestError::RetryAfter(wait) = err {
+                        next_edit = Instant::now() + wait.max(edit_interval);
+                    }
+                }
+            }
+
+            (draft, text)
+        };
+
+        let (response, (draft, text)) =
+            tokio::join!(self.nova_client.stream_prompt(request.clone(), delta_tx), render);
+
+        let response = match (response, &draft) {
+            (Ok(response), _) => response,
+            (Err(err), None) if err.status().is_some_and(nova_helpers::is_streaming_unsupported) => {
+                tracing::info!(status = err.status(), "gateway refused to stream, retrying without streaming");
+                self.nova_client.send_prompt(request).await?
+            }
+            (Err(err), Some(draft)) => {
+                // Don't leave a preview behind that looks like it's still
+                // being written
+                let incomplete = helpers::format_incomplete_answer(&text);
+                if let Err(edit_err) = utils::edit_text(&self.bot, chat_id, draft.id, incomplete, None).await {
+                    tracing::warn!(error = %edit_err, "failed to mark streamed answer as incomplete");
+                }
+                return Err(err.into());
+            }
+            (Err(err), None) => return Err(err.into()),
+        };
+
+        Ok((response, draft))
+    }
+
+    /// Replaces the streamed preview in `draft` with the formatted `reply`,
+    /// sending what doesn't fit as further replies. `keyboard` goes on the
+    /// last part.
+    async fn finish_draft(
+        &self,
+        message: &Message,
+        draft: &Message,
+        reply: &str,
+        keyboard: Option<InlineKeyboardMarkup>,
+    ) -> Result<Vec<MessageId>, BotError> {
+        let parts = utils::split_markdown(reply);
+        let last = parts.len().saturating_sub(1);
+        let mut sent = Vec::new();
+        for (index, part) in parts.into_iter().enumerate() {
+            let keyboard = keyboard.clone().filter(|_| index == last);
+            if index == 0 {
+                utils::edit_formatted(&self.bot, draft.chat.id, draft.id, &part, keyboard).await?;
+                sent.push(draft.id);
+            } else {
+                let to = Destination::of(message);
+                sent.push(utils::send_formatted_reply(&self.bot, to, &part, message.id, keyboard).await?.id);
+            }
+        }
+        Ok(sent)
+    }
+
+    /// Loads the chat state, assigning the chat its conversation reference
+    /// on first use.
+    async fn ensure_state(&self, chat_id: ChatId) -> Result<ChatState, BotError> {
+        let state = self
+            .store
+            .update(
+                chat_id.0,
+                Box::new(move |state| {
+                    state.ref_id.get_or_insert_with(|| chat_id.0.to_string());
+                }),
+            )
+            .await?;
+        Ok(state)
     }
 }
 
 impl BotError {
     fn user_message(&self) -> Option<String> {
         match self {
-            BotError::Telegram(_) => None,
-            BotError::Nova(err) => Some(format!("Nova Gateway error: {err}")),
+            BotError::Telegram(_) | BotError::Store(_) => None,
+            BotError::Nova(err) => Some(nova_error_message(err)),
+            BotError::Download(_) => Some("I couldn't download that image. Please try sending it again.".to_string()),
             BotError::MissingMessageText => Some("Please provide a message after /chat. Example: /chat Hello, how are you?".to_string()),
+            BotError::InvalidArgument(usage) => Some(usage.clone()),
+            BotError::Conversation(err) => Some(err.to_string()),
+            BotError::AdminRequired => Some("Only chat administrators can change this setting.".to_string()),
+            BotError::BotAdminRequired => Some("Only bot administrators can manage access.".to_string()),
+            BotError::RateLimited(wait) => Some(format!(
+                "You're sending messages too quickly. Please wait {} seconds and try again.",
+                wait.as_secs_f64().ceil().max(1.0) as u64
+            )),
+            BotError::QuotaExceeded { scope, period } => {
+                Some(helpers::format_quota_exceeded(*scope, *period, Utc::now()))
+            }
+            BotError::AccessDenied => {
+                Some("You don't have access to this bot. Ask the bot owner to grant it.".to_string())
+            }
+        }
+    }
+}
+
+/// What users are told about a failed gateway call. The details stay in the
+/// logs; users only learn whether retrying can help.
+fn nova_error_message(error: &NovaClientError) -> String {
+    match error {
+        NovaClientError::Unauthorized { .. } => {
+            "The bot can't sign in to Nova right now. Please let the bot owner know.".to_string()
+        }
+        NovaClientError::QuotaExhausted { .. } => {
+            "The bot has used up its Nova balance. Please let the bot owner know.".to_string()
+        }
+        NovaClientError::RateLimited { retry_after: Some(wait), .. } => {
+            format!("Nova is busy right now. Please try again in {} seconds.", wait.as_secs().max(1))
+        }
+        NovaClientError::RateLimited { retry_after: None, .. } => {
+            "Nova is busy right now. Please try again in a moment.".to_string()
+        }
+        NovaClientError::BadRequest { .. } => {
+            "Nova couldn't process this request. Try rephrasing or shortening your message.".to_string()
+        }
+        NovaClientError::Timeout => "Nova took too long to answer. Please try again.".to_string(),
+        NovaClientError::IncompleteStream => "Nova's answer was cut off. Please try again.".to_string(),
+        NovaClientError::Unavailable => {
+            "Nova is temporarily unavailable. Please try again in a minute.".to_string()
+        }
+        NovaClientError::Upstream { .. } | NovaClientError::Http(_) | NovaClientError::Header(_) => {
+            "Nova is having trouble right now. Please try again later.".to_string()
         }
     }
 }
@@ -1,4 +1,31 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::{DateTime, Datelike, Months, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
 use teloxide::utils::command::BotCommands;
+use thiserror::Error;
+
+use crate::config::dto::{AccessControl, QuotaLimits, ReasoningSettings, ThreadPolicy, TokenBounds};
+
+/// Messages remembered per chat for [`ReplyThreads`].
+const MAX_TRACKED_MESSAGES: usize = 1000;
+
+/// Derived `ref_id`s remembered per conversation, see [`Conversation`].
+const MAX_DERIVED_REFS: usize = 200;
+
+/// Answers remembered per chat for [`AnsweredPrompts`].
+const MAX_TRACKED_ANSWERS: usize = 50;
+
+/// Name of the conversation every chat starts with.
+pub const DEFAULT_CONVERSATION: &str = "default";
+
+/// Named conversations a chat can keep besides its default one.
+pub const MAX_CONVERSATIONS: usize = 20;
+
+const MAX_CONVERSATION_NAME_CHARS: usize = 32;
+
+/// Values offered for verbosity and reasoning effort in `/settings`.
+pub const SETTING_LEVELS: [&str; 3] = ["Low", "Medium", "High"];
 
 #[derive(Debug, Clone, BotCommands)]
 #[command(rename_rule = "lowercase", description = "Available commands:")]
@@ -9,9 +36,738 @@ pub enum BotCommand {
     Reset,
     #[command(description = "Chat with Nova Gateway")]
     Chat,
+    #[command(description = "Toggle answering @mentions and replies in this group (on/off)")]
+    Mentions(String),
+    #[command(description = "Choose who shares a conversation in this group (shared/user/reply)")]
+    Threads(String),
+    #[command(description = "Show or change the Nova model used in this chat")]
+    Model,
+    #[command(description = "Change verbosity, reasoning and reply length for this chat")]
+    Settings,
+    #[command(description = "Show, set or clear the persona for this chat (/persona <preset or prompt> | clear)")]
+    Persona(String),
+    #[command(description = "Show how much of your and this chat's usage allowance is left")]
+    Usage,
+    #[command(description = "Start a new named conversation (/new [name])")]
+    New(String),
+    #[command(description = "Switch to another conversation (/switch <name>)")]
+    Switch(String),
+    #[command(description = "List this chat's conversations")]
+    List,
+    #[command(description = "Rename a conversation (/rename <name> <new name>)")]
+    Rename(String),
+    #[command(description = "Delete a conversation and its history (/delete <name>)")]
+    Delete(String),
+    #[command(description = "off")]
+    Grant(String),
+    #[command(description = "off")]
+    Revoke(String),
+    #[command(description = "off")]
+    Access(String),
+}
+
+impl BotCommand {
+    /// Command name without arguments, used as a metrics label.
+    pub fn name(&self) -> &'static str {
+        match self {
+            BotCommand::Help => "help",
+            BotCommand::Reset => "reset",
+            BotCommand::Chat => "chat",
+            BotCommand::Mentions(_) => "mentions",
+            BotCommand::Threads(_) => "threads",
+            BotCommand::Model => "model",
+            BotCommand::Settings => "settings",
+            BotCommand::Persona(_) => "persona",
+            BotCommand::Usage => "usage",
+            BotCommand::New(_) => "new",
+            BotCommand::Switch(_) => "switch",
+            BotCommand::List => "list",
+            BotCommand::Rename(_) => "rename",
+            BotCommand::Delete(_) => "delete",
+            BotCommand::Grant(_) => "grant",
+            BotCommand::Revoke(_) => "revoke",
+            BotCommand::Access(_) => "access",
+        }
+    }
+}
+
+/// Actions behind inline keyboard buttons, encoded in the callback data as
+/// `<kind>:<value>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CallbackAction {
+    SetModel(String),
+    Setting(SettingChange),
+    /// Picks a persona preset by name; `None` clears the persona.
+    SetPersona(Option<String>),
+    Answer(AnswerAction),
+}
+
+/// Buttons under an answer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnswerAction {
+    /// Asks the same prompt again and replaces the answer.
+    Regenerate,
+    /// Asks Nova to go on and posts the rest as a new reply.
+    Continue,
+    /// Asks for a condensed version and replaces the answer.
+    Shorter,
+}
+
+impl AnswerAction {
+    pub const ALL: [AnswerAction; 3] = [AnswerAction::Regenerate, AnswerAction::Continue, AnswerAction::Shorter];
+
+    pub fn parse(value: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|action| action.data() == value)
+    }
+
+    fn data(self) -> &'static str {
+        match self {
+            AnswerAction::Regenerate => "regenerate",
+            AnswerAction::Continue => "continue",
+            AnswerAction::Shorter => "shorter",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            AnswerAction::Regenerate => "Regenerate",
+            AnswerAction::Continue => "Continue",
+            AnswerAction::Shorter => "Shorter",
+        }
+    }
+}
+
+/// A change made from the `/settings` menu, encoded as `<setting>=<value>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SettingChange {
+    Verbosity(String),
+    Reasoning(bool),
+    Effort(String),
+    MaxTokens(u32),
+    Reset,
+}
+
+impl CallbackAction {
+    pub fn parse(data: &str) -> Option<Self> {
+        let (kind, value) = data.split_once(':')?;
+        match kind {
+            "model" => Some(CallbackAction::SetModel(value.to_string())),
+            "settings" => SettingChange::parse(value).map(CallbackAction::Setting),
+            "persona" if value.is_empty() => Some(CallbackAction::SetPersona(None)),
+            "persona" => Some(CallbackAction::SetPersona(Some(value.to_string()))),
+            "answer" => AnswerAction::parse(value).map(CallbackAction::Answer),
+            _ => None,
+        }
+    }
+
+    pub fn data(&self) -> String {
+        match self {
+            CallbackAction::SetModel(model) => format!("model:{model}"),
+            CallbackAction::Setting(change) => format!("settings:{}", change.data()),
+            CallbackAction::SetPersona(name) => format!("persona:{}", name.as_deref().unwrap_or_default()),
+            CallbackAction::Answer(action) => format!("answer:{}", action.data()),
+        }
+    }
+
+    /// Action name without its value, used as a metrics label.
+    pub fn name(&self) -> &'static str {
+        match self {
+            CallbackAction::SetModel(_) => "model",
+            CallbackAction::Setting(_) => "settings",
+            CallbackAction::SetPersona(_) => "persona",
+            CallbackAction::Answer(_) => "answer",
+        }
+    }
+}
+
+impl SettingChange {
+    /// Parses the value part of the callback data; levels outside
+    /// [`SETTING_LEVELS`] are rejected.
+    pub fn parse(data: &str) -> Option<Self> {
+        if data == "reset" {
+            return Some(SettingChange::Reset);
+        }
+        let (setting, value) = data.split_once('=')?;
+        let level = || SETTING_LEVELS.contains(&value).then(|| value.to_string());
+        match setting {
+            "verbosity" => level().map(SettingChange::Verbosity),
+            "effort" => level().map(SettingChange::Effort),
+            "reasoning" => match value {
+                "on" => Some(SettingChange::Reasoning(true)),
+                "off" => Some(SettingChange::Reasoning(false)),
+                _ => None,
+            },
+            "tokens" => value.parse().ok().map(SettingChange::MaxTokens),
+            _ => None,
+        }
+    }
+
+    fn data(&self) -> String {
+        match self {
+            SettingChange::Verbosity(level) => format!("verbosity={level}"),
+            SettingChange::Reasoning(enabled) => format!("reasoning={}", if *enabled { "on" } else { "off" }),
+            SettingChange::Effort(level) => format!("effort={level}"),
+            SettingChange::MaxTokens(tokens) => format!("tokens={tokens}"),
+            SettingChange::Reset => "reset".to_string(),
+        }
+    }
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ChatState {
     pub ref_id: Option<String>,
+    /// Overrides `BOT_GROUP_TRIGGERS` for this chat when set.
+    pub mention_triggers: Option<bool>,
+    /// Overrides `NOVA_MODEL` for this chat when set.
+    pub model: Option<String>,
+    pub settings: ChatSettings,
+    pub persona: Option<Persona>,
+    /// Usage of everyone in the chat together.
+    pub usage: UsageCounters,
+    /// Overrides `BOT_GROUP_THREAD_POLICY` for this chat when set.
+    pub thread_policy: Option<ThreadPolicy>,
+    pub threads: ReplyThreads,
+    pub conversations: Conversations,
+    pub answers: AnsweredPrompts,
+}
+
+/// Maps message IDs to the first message of the reply chain they belong to,
+/// for [`ThreadPolicy::PerReply`]. Only the most recent messages are kept.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ReplyThreads(BTreeMap<i32, i32>);
+
+impl ReplyThreads {
+    pub fn root_of(&self, message_id: i32) -> Option<i32> {
+        self.0.get(&message_id).copied()
+    }
+
+    pub fn record(&mut self, message_ids: impl IntoIterator<Item = i32>, root: i32) {
+        for message_id in message_ids {
+            self.0.insert(message_id, root);
+        }
+        // Message IDs grow over time, so the smallest ones are the oldest
+        while self.0.len() > MAX_TRACKED_MESSAGES {
+            self.0.pop_first();
+        }
+    }
+}
+
+/// Prompts of the most recent answers, by the ID of the message that
+/// carries the answer's buttons, so the buttons can replay them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct AnsweredPrompts(BTreeMap<i32, AnsweredPrompt>);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AnsweredPrompt {
+    pub prompt: String,
+    /// Messages the answer was sent in, in order.
+    pub message_ids: Vec<i32>,
+}
+
+impl AnsweredPrompts {
+    pub fn get(&self, message_id: i32) -> Option<&AnsweredPrompt> {
+        self.0.get(&message_id)
+    }
+
+    /// Remembers `answer`, forgetting earlier answers it replaced.
+    pub fn record(&mut self, message_id: i32, answer: AnsweredPrompt) {
+        self.0
+            .retain(|_, earlier| !earlier.message_ids.iter().any(|id| answer.message_ids.contains(id)));
+        self.0.insert(message_id, answer);
+        while self.0.len() > MAX_TRACKED_ANSWERS {
+            self.0.pop_first();
+        }
+    }
+}
+
+/// The named conversations of a chat, started with `/new`. The chat's own
+/// `ref_id` is the [`DEFAULT_CONVERSATION`], which cannot be renamed or
+/// deleted.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Conversations {
+    /// Name of the conversation prompts go to; the default one when unset.
+    pub active: Option<String>,
+    pub named: BTreeMap<String, Conversation>,
+    /// When the default conversation was last prompted.
+    pub default_last_used: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Conversation {
+    pub ref_id: String,
+    pub last_used: Option<DateTime<Utc>>,
+    /// The most recently used `ref_id`s derived from `ref_id` for forum
+    /// topics, users or reply threads, so deleting the conversation can
+    /// clear their history too.
+    #[serde(default)]
+    pub derived_refs: Vec<String>,
+}
+
+impl Conversation {
+    /// All `ref_id`s the conversation's history is kept under.
+    pub fn refs(&self) -> impl Iterator<Item = &String> {
+        std::iter::once(&self.ref_id).chain(&self.derived_refs)
+    }
+
+    fn owns(&self, ref_id: &str) -> bool {
+        ref_id
+            .strip_prefix(self.ref_id.as_str())
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with(':'))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ConversationError {
+    #[error("Conversation names are single words of at most {MAX_CONVERSATION_NAME_CHARS} characters.")]
+    InvalidName,
+    #[error("There is already a conversation called {0}.")]
+    NameTaken(String),
+    #[error("There is no conversation called {0}. Use /list to see them.")]
+    NotFound(String),
+    #[error("The default conversation can't be renamed or deleted.")]
+    DefaultConversation,
+    #[error("This chat already has {MAX_CONVERSATIONS} conversations. Delete one with /delete first.")]
+    TooMany,
+}
+
+impl Conversations {
+    pub fn active_name(&self) -> &str {
+        self.active.as_deref().unwrap_or(DEFAULT_CONVERSATION)
+    }
+
+    /// Nova `ref_id` of the active conversation; `default_ref` is the one
+    /// of the default conversation.
+    pub fn active_ref<'a>(&'a self, default_ref: &'a str) -> &'a str {
+        self.active
+            .as_ref()
+            .and_then(|name| self.named.get(name))
+            .map_or(default_ref, |conversation| &conversation.ref_id)
+    }
+
+    /// Marks the conversation a prompt sent under `ref_id` belongs to as
+    /// used, remembering `ref_id` when it is a derived one.
+    pub fn touch(&mut self, now: DateTime<Utc>, ref_id: &str) {
+        let Some(conversation) = self.named.values_mut().find(|conversation| conversation.owns(ref_id)) else {
+            self.default_last_used = Some(now);
+            return;
+        };
+        conversation.last_used = Some(now);
+        if ref_id != conversation.ref_id {
+            conversation.derived_refs.retain(|derived| derived != ref_id);
+            conversation.derived_refs.push(ref_id.to_string());
+            if conversation.derived_refs.len() > MAX_DERIVED_REFS {
+                conversation.derived_refs.remove(0);
+            }
+        }
+    }
+
+    /// First unused name of the form `chat-<n>`.
+    pub fn next_name(&self) -> String {
+        (2..)
+            .map(|n| format!("chat-{n}"))
+            .find(|name| !self.named.contains_key(name))
+            .unwrap_or_default()
+    }
+
+    /// Adds a conversation and makes it the active one.
+    pub fn create(&mut self, name: &str, ref_id: String) -> Result<(), ConversationError> {
+        self.check_new_name(name)?;
+        if self.named.len() >= MAX_CONVERSATIONS {
+            return Err(ConversationError::TooMany);
+        }
+        self.named.insert(
+            name.to_string(),
+            Conversation {
+                ref_id,
+                last_used: None,
+                derived_refs: Vec::new(),
+            },
+        );
+        self.active = Some(name.to_string());
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Result<&Conversation, ConversationError> {
+        if name == DEFAULT_CONVERSATION {
+            return Err(ConversationError::DefaultConversation);
+        }
+        self.named
+            .get(name)
+            .ok_or_else(|| ConversationError::NotFound(name.to_string()))
+    }
+
+    pub fn switch(&mut self, name: &str) -> Result<(), ConversationError> {
+        if name == DEFAULT_CONVERSATION {
+            self.active = None;
+        } else if self.named.contains_key(name) {
+            self.active = Some(name.to_string());
+        } else {
+            return Err(ConversationError::NotFound(name.to_string()));
+        }
+        Ok(())
+    }
+
+    pub fn rename(&mut self, name: &str, new_name: &str) -> Result<(), ConversationError> {
+        if name == DEFAULT_CONVERSATION {
+            return Err(ConversationError::DefaultConversation);
+        }
+        self.check_new_name(new_name)?;
+        let conversation = self
+            .named
+            .remove(name)
+            .ok_or_else(|| ConversationError::NotFound(name.to_string()))?;
+        self.named.insert(new_name.to_string(), conversation);
+        if self.active.as_deref() == Some(name) {
+            self.active = Some(new_name.to_string());
+        }
+        Ok(())
+    }
+
+    /// Removes a conversation; deleting the active one switches back to the
+    /// default conversation.
+    pub fn remove(&mut self, name: &str) -> Result<Conversation, ConversationError> {
+        if name == DEFAULT_CONVERSATION {
+            return Err(ConversationError::DefaultConversation);
+        }
+        let conversation = self
+            .named
+            .remove(name)
+            .ok_or_else(|| ConversationError::NotFound(name.to_string()))?;
+        if self.active.as_deref() == Some(name) {
+            self.active = None;
+        }
+        Ok(conversation)
+    }
+
+    fn check_new_name(&self, name: &str) -> Result<(), ConversationError> {
+        let length = name.chars().count();
+        if length == 0 || length > MAX_CONVERSATION_NAME_CHARS || name.contains(char::is_whitespace) {
+            return Err(ConversationError::InvalidName);
+        }
+        if name == DEFAULT_CONVERSATION || self.named.contains_key(name) {
+            return Err(ConversationError::NameTaken(name.to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// What the bot remembers per Telegram user, across chats.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UserState {
+    pub usage: UsageCounters,
+}
+
+/// Whose allowance a quota applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsageScope {
+    User,
+    Chat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuotaPeriod {
+    Daily,
+    Monthly,
+}
+
+impl QuotaPeriod {
+    /// First day (at 00:00 UTC) of the next period after `today`.
+    pub fn next_reset(self, today: NaiveDate) -> NaiveDate {
+        match self {
+            QuotaPeriod::Daily => today.succ_opt().unwrap_or(today),
+            QuotaPeriod::Monthly => {
+                let month_start = today.with_day(1).unwrap_or(today);
+                month_start.checked_add_months(Months::new(1)).unwrap_or(today)
+            }
+        }
+    }
+}
+
+/// Prompts and tokens used in the current UTC day and month.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UsageCounters {
+    /// Day the daily counters belong to.
+    pub day: Option<NaiveDate>,
+    pub daily_prompts: u64,
+    pub daily_tokens: u64,
+    /// First day of the month the monthly counters belong to.
+    pub month: Option<NaiveDate>,
+    pub monthly_prompts: u64,
+    pub monthly_tokens: u64,
+}
+
+impl UsageCounters {
+    /// The counters as of `today`, with those of ended periods reset.
+    pub fn current(&self, today: NaiveDate) -> UsageCounters {
+        let mut counters = self.clone();
+        if counters.day != Some(today) {
+            counters.day = Some(today);
+            counters.daily_prompts = 0;
+            counters.daily_tokens = 0;
+        }
+        let month = today.with_day(1);
+        if counters.month != month {
+            counters.month = month;
+            counters.monthly_prompts = 0;
+            counters.monthly_tokens = 0;
+        }
+        counters
+    }
+
+    pub fn record(&mut self, today: NaiveDate, tokens: u64) {
+        *self = self.current(today);
+        self.daily_prompts += 1;
+        self.daily_tokens += tokens;
+        self.monthly_prompts += 1;
+        self.monthly_tokens += tokens;
+    }
+
+    /// The longest period whose prompt or token cap is used up, since that
+    /// is the one that decides when prompting is possible again.
+    pub fn exhausted(&self, limits: &QuotaLimits, today: NaiveDate) -> Option<QuotaPeriod> {
+        let usage = self.current(today);
+        let reached = |used: u64, limit: Option<u64>| limit.is_some_and(|limit| used >= limit);
+
+        if reached(usage.monthly_prompts, limits.monthly_prompts) || reached(usage.monthly_tokens, limits.monthly_tokens)
+        {
+            Some(QuotaPeriod::Monthly)
+        } else if reached(usage.daily_prompts, limits.daily_prompts) || reached(usage.daily_tokens, limits.daily_tokens) {
+            Some(QuotaPeriod::Daily)
+        } else {
+            None
+        }
+    }
+}
+
+/// System prompt sent with every request from a chat.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Persona {
+    /// A preset from `BOT_PERSONAS`, looked up on every request so edits to
+    /// the preset apply right away.
+    Preset { name: String },
+    Custom { prompt: String },
+}
+
+/// Per-chat overrides of the request settings from `Config`, changed with
+/// `/settings`. Unset fields fall back to the configured defaults.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChatSettings {
+    pub verbosity: Option<String>,
+    pub max_tokens: Option<u32>,
+    pub reasoning: Option<bool>,
+    pub reasoning_effort: Option<String>,
+}
+
+impl ChatSettings {
+    pub fn apply(&mut self, change: SettingChange) {
+        match change {
+            SettingChange::Verbosity(level) => self.verbosity = Some(level),
+            SettingChange::Reasoning(enabled) => self.reasoning = Some(enabled),
+            SettingChange::Effort(level) => self.reasoning_effort = Some(level),
+            SettingChange::MaxTokens(tokens) => self.max_tokens = Some(tokens),
+            SettingChange::Reset => *self = ChatSettings::default(),
+        }
+    }
+
+    pub fn verbosity<'a>(&'a self, default: &'a str) -> &'a str {
+        self.verbosity.as_deref().unwrap_or(default)
+    }
+
+    /// The chat's token cap, kept within `bounds` in case they were
+    /// narrowed after it was set.
+    pub fn max_tokens(&self, default: u32, bounds: TokenBounds) -> u32 {
+        self.max_tokens.map_or(default, |tokens| bounds.clamp(tokens))
+    }
+
+    pub fn reasoning(&self, defaults: &ReasoningSettings) -> ReasoningSettings {
+        ReasoningSettings {
+            enabled: self.reasoning.unwrap_or(defaults.enabled),
+            effort: self.reasoning_effort.clone().or_else(|| defaults.effort.clone()),
+        }
+    }
+}
+
+/// Users and chats granted or revoked access at runtime with `/grant` and
+/// `/revoke`. Positive IDs are users, negative IDs are group chats.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AccessList {
+    /// Set with `/access restrict`: from then on only allowed users and
+    /// chats may use the bot, until it is lifted with `/access open`.
+    pub restricted: bool,
+    pub allowed: BTreeSet<i64>,
+    pub denied: BTreeSet<i64>,
+}
+
+impl AccessList {
+    pub fn grant(&mut self, id: i64) {
+        self.denied.remove(&id);
+        self.allowed.insert(id);
+    }
+
+    /// Limits the bot to allowed users and chats.
+    pub fn restrict(&mut self) {
+        self.restricted = true;
+    }
+
+    /// Lifts the restriction set by [`AccessList::restrict`]. Grants,
+    /// revocations and the configured allowlists are kept.
+    pub fn open(&mut self) {
+        self.restricted = false;
+    }
+
+    /// Whether only allowed users and chats may use the bot.
+    pub fn is_restricted(&self, config: &AccessControl) -> bool {
+        self.restricted || !config.allowed_users.is_empty() || !config.allowed_chats.is_empty()
+    }
+
+    pub fn revoke(&mut self, id: i64) {
+        self.allowed.remove(&id);
+        self.denied.insert(id);
+    }
+
+    /// Whether `user_id` may use the bot in `chat_id`. Admins always may;
+    /// anyone denied, or in a denied chat, is blocked. Otherwise, once access
+    /// is restricted, only allowed users and members of allowed chats may.
+    pub fn permits(&self, config: &AccessControl, user_id: Option<i64>, chat_id: i64) -> AccessDecision {
+        if user_id.is_some_and(|user_id| config.admins.contains(&user_id)) {
+            return AccessDecision::Allowed;
+        }
+
+        let denied = |id: i64| self.denied.contains(&id);
+        let user_denied = user_id.is_some_and(|user_id| denied(user_id) || config.denied_users.contains(&user_id));
+        if user_denied || denied(chat_id) || config.denied_chats.contains(&chat_id) {
+            return AccessDecision::Blocked;
+        }
+
+        let allowed = |id: i64| self.allowed.contains(&id);
+        let permitted = !self.is_restricted(config)
+            || user_id.is_some_and(|user_id| allowed(user_id) || config.allowed_users.contains(&user_id))
+            || allowed(chat_id)
+            || config.allowed_chats.contains(&chat_id);
+        if permitted { AccessDecision::Allowed } else { AccessDecision::NotAllowed }
+    }
+}
+
+/// Outcome of [`AccessList::permits`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessDecision {
+    Allowed,
+    /// The user or chat is on a denylist; the bot ignores them silently.
+    Blocked,
+    /// Access is restricted and the user or chat is not on an allowlist.
+    NotAllowed,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn records_prompts_and_tokens() {
+        let mut usage = UsageCounters::default();
+        usage.record(date(2026, 3, 14), 100);
+        usage.record(date(2026, 3, 14), 50);
+        assert_eq!(
+            usage,
+            UsageCounters {
+                day: Some(date(2026, 3, 14)),
+                daily_prompts: 2,
+                daily_tokens: 150,
+                month: Some(date(2026, 3, 1)),
+                monthly_prompts: 2,
+                monthly_tokens: 150,
+            }
+        );
+    }
+
+    #[test]
+    fn resets_daily_counters_on_a_new_day() {
+        let mut usage = UsageCounters::default();
+        usage.record(date(2026, 3, 14), 100);
+        usage.record(date(2026, 3, 15), 10);
+        assert_eq!((usage.daily_prompts, usage.daily_tokens), (1, 10));
+        assert_eq!((usage.monthly_prompts, usage.monthly_tokens), (2, 110));
+
+        let limits = QuotaLimits { daily_prompts: Some(1), monthly_prompts: Some(3), ..QuotaLimits::default() };
+        assert_eq!(usage.exhausted(&limits, date(2026, 3, 15)), Some(QuotaPeriod::Daily));
+        assert_eq!(usage.exhausted(&limits, date(2026, 3, 16)), None);
+    }
+
+    #[test]
+    fn resets_all_counters_on_a_new_month() {
+        let mut usage = UsageCounters::default();
+        usage.record(date(2026, 3, 31), 100);
+        let next_month = usage.current(date(2026, 4, 1));
+        assert_eq!((next_month.daily_prompts, next_month.monthly_prompts), (0, 0));
+        assert_eq!(next_month.month, Some(date(2026, 4, 1)));
+
+        // The same day of another month is a new day too
+        let year_later = usage.current(date(2027, 3, 31));
+        assert_eq!((year_later.daily_prompts, year_later.monthly_prompts), (0, 0));
+    }
+
+    #[test]
+    fn reports_each_exhausted_limit() {
+        let today = date(2026, 3, 14);
+        let mut usage = UsageCounters::default();
+        usage.record(today, 100);
+
+        let cases = [
+            (QuotaLimits { daily_prompts: Some(1), ..QuotaLimits::default() }, QuotaPeriod::Daily),
+            (QuotaLimits { daily_tokens: Some(100), ..QuotaLimits::default() }, QuotaPeriod::Daily),
+            (QuotaLimits { monthly_prompts: Some(1), ..QuotaLimits::default() }, QuotaPeriod::Monthly),
+            (QuotaLimits { monthly_tokens: Some(100), ..QuotaLimits::default() }, QuotaPeriod::Monthly),
+        ];
+        for (limits, period) in cases {
+            assert_eq!(usage.exhausted(&limits, today), Some(period), "{limits:?}");
+        }
+
+        let roomy = QuotaLimits {
+            daily_prompts: Some(2),
+            daily_tokens: Some(101),
+            monthly_prompts: Some(2),
+            monthly_tokens: Some(101),
+        };
+        assert_eq!(usage.exhausted(&roomy, today), None);
+        assert_eq!(usage.exhausted(&QuotaLimits::default(), today), None);
+    }
+
+    #[test]
+    fn prefers_the_monthly_period_when_both_are_exhausted() {
+        let today = date(2026, 3, 14);
+        let mut usage = UsageCounters::default();
+        usage.record(today, 100);
+        let limits = QuotaLimits {
+            daily_prompts: Some(1),
+            monthly_prompts: Some(1),
+            ..QuotaLimits::default()
+        };
+        assert_eq!(usage.exhausted(&limits, today), Some(QuotaPeriod::Monthly));
+    }
+
+    #[test]
+    fn frees_exhausted_limits_when_the_period_rolls_over() {
+        let mut usage = UsageCounters::default();
+        usage.record(date(2026, 3, 31), 100);
+        let daily = QuotaLimits { daily_prompts: Some(1), ..QuotaLimits::default() };
+        let monthly = QuotaLimits { monthly_prompts: Some(1), ..QuotaLimits::default() };
+
+        assert_eq!(usage.exhausted(&daily, date(2026, 3, 31)), Some(QuotaPeriod::Daily));
+        assert_eq!(usage.exhausted(&monthly, date(2026, 3, 31)), Some(QuotaPeriod::Monthly));
+        assert_eq!(usage.exhausted(&daily, date(2026, 4, 1)), None);
+        assert_eq!(usage.exhausted(&monthly, date(2026, 4, 1)), None);
+    }
 }
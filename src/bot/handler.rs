@@ -1,6 +1,9 @@
 use std::sync::Arc;
 
-use teloxide::{types::Message, RequestError};
+use teloxide::{types::{CallbackQuery, ChatId, Me, Message, Update}, RequestError};
+use tracing::{Instrument, Span};
+
+use crate::{metrics::METRICS, telemetry, utils::Destination};
 
 use super::{controller::{BotController, BotError}, dto::BotCommand};
 
@@ -8,29 +11,95 @@ pub type HandlerResult = Result<(), RequestError>;
 
 pub async fn handle_command_update(
     controller: Arc<BotController>,
+    update: Update,
     message: Message,
     command: BotCommand,
 ) -> HandlerResult {
-    match controller.handle_command(&message, command).await {
-        Ok(()) => Ok(()),
-        Err(BotError::Telegram(err)) => Err(err),
-        Err(other) => {
-            controller.notify_error(message.chat.id, &other).await?;
-            Ok(())
-        }
-    }
+    let correlation_id = telemetry::new_correlation_id();
+    let span = update_span(&update, &message, Some(command.name()), &correlation_id);
+
+    let handling = async {
+        tracing::info!("handling command");
+        let result = controller.handle_command(&message, command).await;
+        report_result(&controller, Destination::of(&message), Some(&message), result).await
+    };
+    telemetry::with_correlation_id(correlation_id, handling.instrument(span)).await
 }
 
 pub async fn handle_message_update(
     controller: Arc<BotController>,
+    update: Update,
     message: Message,
+    me: Me,
+) -> HandlerResult {
+    let correlation_id = telemetry::new_correlation_id();
+    let span = update_span(&update, &message, None, &correlation_id);
+
+    let handling = async {
+        tracing::debug!("handling message");
+        let result = controller.handle_text_message(&message, &me).await;
+        report_result(&controller, Destination::of(&message), Some(&message), result).await
+    };
+    telemetry::with_correlation_id(correlation_id, handling.instrument(span)).await
+}
+
+pub async fn handle_callback_update(
+    controller: Arc<BotController>,
+    update: Update,
+    query: CallbackQuery,
+) -> HandlerResult {
+    let correlation_id = telemetry::new_correlation_id();
+    let span = tracing::info_span!(
+        "update",
+        update_id = update.id,
+        chat_id = query.message.as_ref().map(|message| message.chat.id.0),
+        user_id = query.from.id.0,
+        callback = query.data.as_deref(),
+        correlation_id,
+    );
+
+    let handling = async {
+        tracing::info!("handling callback query");
+        let result = controller.handle_callback(&query).await;
+        match &query.message {
+            Some(message) => report_result(&controller, Destination::of(message), Some(message), result).await,
+            None => report_result(&controller, ChatId(query.from.id.0 as i64).into(), None, result).await,
+        }
+    };
+    telemetry::with_correlation_id(correlation_id, handling.instrument(span)).await
+}
+
+fn update_span(update: &Update, message: &Message, command: Option<&str>, correlation_id: &str) -> Span {
+    tracing::info_span!(
+        "update",
+        update_id = update.id,
+        chat_id = message.chat.id.0,
+        user_id = message.from().map(|user| user.id.0),
+        command,
+        correlation_id,
+    )
+}
+
+/// Tells the user about failed requests; Telegram errors are passed on to
+/// the dispatcher since there is no way to report them in the chat.
+async fn report_result(
+    controller: &BotController,
+    to: Destination,
+    cause: Option<&Message>,
+    result: Result<(), BotError>,
 ) -> HandlerResult {
-    match controller.handle_text_message(&message).await {
+    let outcome = match result {
         Ok(()) => Ok(()),
         Err(BotError::Telegram(err)) => Err(err),
         Err(other) => {
-            controller.notify_error(message.chat.id, &other).await?;
-            Ok(())
+            tracing::warn!(error = %other, "failed to handle update");
+            controller.notify_error(to, cause, &other).await
         }
+    };
+
+    if let Err(err) = &outcome {
+        tracing::error!(error = %err, "telegram request failed");
+        METRICS.telegram_failures.inc();
     }
+    outcome
 }
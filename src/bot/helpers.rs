@@ -1,31 +1,420 @@
-use std::borrow::ToOwned;
+use std::{borrow::ToOwned, collections::BTreeMap};
 
-use teloxide::types::Message;
+use chrono::{DateTime, Utc};
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, Me, Message, MessageEntityKind};
 
-use crate::nova::NovaResponse;
+use crate::{
+    config::{
+        dto::{AccessControl, QuotaLimits, ThreadPolicy},
+        Config,
+    },
+    nova::NovaResponse,
+    utils::{Destination, TELEGRAM_MESSAGE_LIMIT},
+};
 
+use super::dto::{
+    AccessList, AnswerAction, CallbackAction, ChatSettings, Conversations, Persona, QuotaPeriod, ReplyThreads, SettingChange,
+    UsageCounters, UsageScope, DEFAULT_CONVERSATION, SETTING_LEVELS,
+};
+
+/// Prompt sent with a photo that has no accompanying text.
+pub const DEFAULT_IMAGE_PROMPT: &str = "Describe this image.";
+
+/// Prompt sent by the Continue button under an answer.
+pub const CONTINUE_PROMPT: &str = "Continue your previous answer exactly where it stopped, without repeating it.";
+
+/// Prompt sent by the Shorter button under an answer.
+pub const SHORTER_PROMPT: &str = "Rewrite your previous answer to be much shorter, keeping only the essentials.";
+
+/// Returns the message text, or the caption for media messages.
 pub fn extract_plain_text(message: &Message) -> Option<String> {
-    message.text().map(ToOwned::to_owned)
+    message.text().or_else(|| message.caption()).map(ToOwned::to_owned)
+}
+
+/// Returns the message text with every mention of the bot removed, or `None`
+/// when the bot is not mentioned.
+pub fn strip_bot_mention(message: &Message, me: &Me) -> Option<String> {
+    let (text, entities) = match message.text() {
+        Some(text) => (text, message.parse_entities()?),
+        None => (message.caption()?, message.parse_caption_entities()?),
+    };
+    let mention = me.mention();
+
+    let mut ranges: Vec<_> = entities
+        .iter()
+        .filter(|entity| match entity.kind() {
+            MessageEntityKind::Mention => entity.text().eq_ignore_ascii_case(&mention),
+            MessageEntityKind::TextMention { user } => user.id == me.user.id,
+            _ => false,
+        })
+        .map(|entity| entity.range())
+        .collect();
+
+    if ranges.is_empty() {
+        return None;
+    }
+
+    ranges.sort_by_key(|range| range.start);
+    let mut stripped = String::with_capacity(text.len());
+    let mut cursor = 0;
+    for range in ranges {
+        stripped.push_str(&text[cursor..range.start]);
+        cursor = range.end;
+    }
+    stripped.push_str(&text[cursor..]);
+
+    Some(stripped.trim().to_string())
+}
+
+/// The message `message` replies to. In forum topics Telegram makes every
+/// message a reply to the topic's creation message; that implicit reply is
+/// not reported.
+pub fn explicit_reply(message: &Message) -> Option<&Message> {
+    let topic = Destination::of(message).thread_id;
+    message
+        .reply_to_message()
+        .filter(|reply| topic != Some(reply.id.0))
+}
+
+pub fn is_reply_to_bot(message: &Message, me: &Me) -> bool {
+    explicit_reply(message)
+        .and_then(|reply| reply.from())
+        .is_some_and(|author| author.id == me.user.id)
+}
+
+/// First message of the reply chain `message` belongs to: its recorded
+/// root when it is already part of a chain (e.g. a prompt replayed by the
+/// answer buttons), else the root of the message it replies to, or the
+/// message itself when it starts a chain.
+pub fn reply_root(message: &Message, threads: &ReplyThreads) -> i32 {
+    if let Some(root) = threads.root_of(message.id.0) {
+        return root;
+    }
+    match explicit_reply(message) {
+        Some(reply) => threads.root_of(reply.id.0).unwrap_or(reply.id.0),
+        None => message.id.0,
+    }
+}
+
+/// Nova `ref_id` of the conversation `message` belongs to, derived from the
+/// chat's own `chat_ref` according to `policy`. Each forum topic is treated
+/// as a chat of its own.
+pub fn conversation_ref(chat_ref: &str, policy: ThreadPolicy, message: &Message, threads: &ReplyThreads) -> String {
+    let chat_ref = match Destination::of(message).thread_id {
+        Some(topic) => format!("{chat_ref}:topic:{topic}"),
+        None => chat_ref.to_string(),
+    };
+    match (policy, message.from()) {
+        (ThreadPolicy::Shared, _) | (ThreadPolicy::PerUser, None) => chat_ref,
+        (ThreadPolicy::PerUser, Some(user)) => format!("{chat_ref}:user:{}", user.id),
+        (ThreadPolicy::PerReply, _) => format!("{chat_ref}:thread:{}", reply_root(message, threads)),
+    }
+}
+
+pub fn format_thread_policy(policy: ThreadPolicy) -> &'static str {
+    match policy {
+        ThreadPolicy::Shared => "Everyone in this chat shares one conversation.",
+        ThreadPolicy::PerUser => "Everyone in this chat has their own conversation.",
+        ThreadPolicy::PerReply => "Every reply thread in this chat is its own conversation.",
+    }
+}
+
+pub fn format_conversation_list(conversations: &Conversations) -> String {
+    fn line(name: &str, active: bool, last_used: Option<DateTime<Utc>>) -> String {
+        let marker = if active { " (active)" } else { "" };
+        let last_used = match last_used {
+            Some(time) => format!("last used {}", time.format("%Y-%m-%d %H:%M UTC")),
+            None => "not used yet".to_string(),
+        };
+        format!("• {name}{marker} - {last_used}")
+    }
+
+    let active = conversations.active_name();
+    let mut lines = vec![
+        "Conversations in this chat:".to_string(),
+        line(DEFAULT_CONVERSATION, active == DEFAULT_CONVERSATION, conversations.default_last_used),
+    ];
+    lines.extend(
+        conversations
+            .named
+            .iter()
+            .map(|(name, conversation)| line(name, active == name, conversation.last_used)),
+    );
+    lines.push("\nUse /switch <name> to change conversations or /new [name] to start one.".to_string());
+    lines.join("\n")
 }
 
 pub fn format_help_text() -> String {
-    vec![
+    [
         "Hello! I'm a Nova Gateway assistant.",
         "\nUse these commands:",
         "/help - Show this help message",
         "/reset - Clear the conversation context",
         "/chat - Chat with Nova Gateway",
+        "/mentions - Toggle answering @mentions and replies in groups",
+        "/threads - Choose who shares a conversation in groups",
+        "/model - Show or change the Nova model",
+        "/settings - Change verbosity, reasoning and reply length",
+        "/persona - Show, set or clear the persona for this chat",
+        "/usage - Show your remaining usage allowance",
+        "/new - Start a new named conversation",
+        "/switch - Switch to another conversation",
+        "/list - List this chat's conversations",
+        "/rename - Rename a conversation",
+        "/delete - Delete a conversation",
         "\nExample: /chat Hello, how are you?",
+        "Send a photo with /chat in the caption to ask about an image.",
     ]
     .join("\n")
 }
 
-pub fn format_nova_response(response: &NovaResponse) -> String {
-    if let Some(text) = response.text.clone() {
-        if !text.trim().is_empty() {
-            return text;
+pub fn format_model_menu(current: &str) -> String {
+    format!("Current model: {current}\nChat administrators can pick another one below.")
+}
+
+pub fn answer_keyboard() -> InlineKeyboardMarkup {
+    let buttons = AnswerAction::ALL
+        .into_iter()
+        .map(|action| InlineKeyboardButton::callback(action.label(), CallbackAction::Answer(action).data()));
+    InlineKeyboardMarkup::new([buttons])
+}
+
+/// One button per allowed model; the current one is ticked.
+pub fn model_keyboard(models: &[String], current: &str) -> InlineKeyboardMarkup {
+    let rows = models.iter().map(|model| {
+        let label = if model == current { format!("✓ {model}") } else { model.clone() };
+        let data = CallbackAction::SetModel(model.clone()).data();
+        vec![InlineKeyboardButton::callback(label, data)]
+    });
+    InlineKeyboardMarkup::new(rows)
+}
+
+pub fn format_settings_menu(settings: &ChatSettings, config: &Config) -> String {
+    let reasoning = settings.reasoning(config.reasoning());
+    let bounds = config.token_bounds();
+    [
+        "Settings for this chat:".to_string(),
+        format!("Verbosity: {}", settings.verbosity(config.nova_verbosity())),
+        format!("Reasoning: {}", if reasoning.enabled { "on" } else { "off" }),
+        format!("Reasoning effort: {}", reasoning.effort.as_deref().unwrap_or("default")),
+        format!(
+            "Max tokens: {} (allowed {}–{})",
+            settings.max_tokens(config.nova_max_tokens(), bounds),
+            bounds.min,
+            bounds.max
+        ),
+        "\nChat administrators can change them below.".to_string(),
+    ]
+    .join("\n")
+}
+
+pub fn settings_keyboard(settings: &ChatSettings, config: &Config) -> InlineKeyboardMarkup {
+    let button = |label: String, change: SettingChange| {
+        InlineKeyboardButton::callback(label, CallbackAction::Setting(change).data())
+    };
+    let tick = |label: &str, selected: bool| if selected { format!("✓ {label}") } else { label.to_string() };
+
+    let verbosity = settings.verbosity(config.nova_verbosity());
+    let reasoning = settings.reasoning(config.reasoning());
+    let bounds = config.token_bounds();
+    let tokens = settings.max_tokens(config.nova_max_tokens(), bounds);
+
+    let verbosity_row = SETTING_LEVELS
+        .iter()
+        .map(|level| {
+            let label = tick(&format!("Verbosity: {level}"), verbosity.eq_ignore_ascii_case(level));
+            button(label, SettingChange::Verbosity(level.to_string()))
+        })
+        .collect();
+    let effort_row = SETTING_LEVELS
+        .iter()
+        .map(|level| {
+            let selected = reasoning.effort.as_deref().is_some_and(|effort| effort.eq_ignore_ascii_case(level));
+            button(tick(&format!("Effort: {level}"), selected), SettingChange::Effort(level.to_string()))
+        })
+        .collect();
+    let reasoning_row = vec![button(
+        format!("Reasoning: {}", if reasoning.enabled { "turn off" } else { "turn on" }),
+        SettingChange::Reasoning(!reasoning.enabled),
+    )];
+
+    let mut tokens_row = Vec::new();
+    let fewer = bounds.clamp(tokens / 2);
+    let more = bounds.clamp(tokens.saturating_mul(2));
+    if fewer != tokens {
+        tokens_row.push(button(format!("Tokens: {fewer}"), SettingChange::MaxTokens(fewer)));
+    }
+    if more != tokens {
+        tokens_row.push(button(format!("Tokens: {more}"), SettingChange::MaxTokens(more)));
+    }
+
+    let reset_row = vec![button("Reset to defaults".to_string(), SettingChange::Reset)];
+    InlineKeyboardMarkup::new([verbosity_row, reasoning_row, effort_row, tokens_row, reset_row])
+}
+
+pub fn format_persona(persona: Option<&Persona>, presets: &BTreeMap<String, String>) -> String {
+    let mut lines = vec![match persona {
+        None => "No persona is set for this chat.".to_string(),
+        Some(Persona::Preset { name }) => match presets.get(name) {
+            Some(prompt) => format!("Persona: {name}\n\n{prompt}"),
+            None => format!("Persona: {name} (this preset no longer exists, so none is used)"),
+        },
+        Some(Persona::Custom { prompt }) => format!("Custom persona:\n\n{prompt}"),
+    }];
+
+    lines.push("\nUse /persona <preset or prompt> to set one, or /persona clear to remove it.".to_string());
+    if !presets.is_empty() {
+        let names: Vec<_> = presets.keys().map(String::as_str).collect();
+        lines.push(format!("Presets: {}", names.join(", ")));
+    }
+    lines.join("\n")
+}
+
+/// One button per persona preset, plus a clear button when a persona is set.
+/// `None` when there would be no buttons at all.
+pub fn persona_keyboard(persona: Option<&Persona>, presets: &BTreeMap<String, String>) -> Option<InlineKeyboardMarkup> {
+    let mut rows: Vec<_> = presets
+        .keys()
+        .map(|name| {
+            let selected = matches!(persona, Some(Persona::Preset { name: current }) if current == name);
+            let label = if selected { format!("✓ {name}") } else { name.clone() };
+            let data = CallbackAction::SetPersona(Some(name.clone())).data();
+            vec![InlineKeyboardButton::callback(label, data)]
+        })
+        .collect();
+
+    if persona.is_some() {
+        let data = CallbackAction::SetPersona(None).data();
+        rows.push(vec![InlineKeyboardButton::callback("Clear persona", data)]);
+    }
+    (!rows.is_empty()).then(|| InlineKeyboardMarkup::new(rows))
+}
+
+pub fn format_access_list(config: &AccessControl, access: &AccessList) -> String {
+    fn ids<'a>(ids: impl IntoIterator<Item = &'a i64>) -> String {
+        let mut ids: Vec<_> = ids.into_iter().copied().collect();
+        if ids.is_empty() {
+            return "none".to_string();
+        }
+        ids.sort_unstable();
+        ids.iter().map(i64::to_string).collect::<Vec<_>>().join(", ")
+    }
+
+    [
+        if access.is_restricted(config) {
+            "Only allowed users and chats can use the bot.".to_string()
+        } else {
+            "Everyone who isn't blocked can use the bot.".to_string()
+        },
+        format!("\nAdmins: {}", ids(&config.admins)),
+        format!("Allowed users (config): {}", ids(&config.allowed_users)),
+        format!("Allowed chats (config): {}", ids(&config.allowed_chats)),
+        format!("Denied users (config): {}", ids(&config.denied_users)),
+        format!("Denied chats (config): {}", ids(&config.denied_chats)),
+        format!("Granted with /grant: {}", ids(&access.allowed)),
+        format!("Revoked with /revoke: {}", ids(&access.denied)),
+    ]
+    .join("\n")
+}
+
+pub fn format_usage(
+    user: Option<(&UsageCounters, &QuotaLimits)>,
+    chat: (&UsageCounters, &QuotaLimits),
+    now: DateTime<Utc>,
+) -> String {
+    let today = now.date_naive();
+    let mut sections = Vec::new();
+    if let Some((usage, limits)) = user {
+        sections.push(format_usage_section("Your usage", &usage.current(today), limits, now));
+    }
+    let (usage, limits) = chat;
+    sections.push(format_usage_section("This chat's usage", &usage.current(today), limits, now));
+    sections.join("\n\n")
+}
+
+fn format_usage_section(title: &str, usage: &UsageCounters, limits: &QuotaLimits, now: DateTime<Utc>) -> String {
+    fn amount(used: u64, limit: Option<u64>, unit: &str) -> String {
+        match limit {
+            Some(limit) => format!("{used} of {limit} {unit} ({} left)", limit.saturating_sub(used)),
+            None => format!("{used} {unit}"),
         }
     }
 
+    [
+        format!("{title}:"),
+        format!(
+            "Today: {}, {} (resets {})",
+            amount(usage.daily_prompts, limits.daily_prompts, "prompts"),
+            amount(usage.daily_tokens, limits.daily_tokens, "tokens"),
+            format_reset(QuotaPeriod::Daily, now)
+        ),
+        format!(
+            "This month: {}, {} (resets {})",
+            amount(usage.monthly_prompts, limits.monthly_prompts, "prompts"),
+            amount(usage.monthly_tokens, limits.monthly_tokens, "tokens"),
+            format_reset(QuotaPeriod::Monthly, now)
+        ),
+    ]
+    .join("\n")
+}
+
+pub fn format_quota_exceeded(scope: UsageScope, period: QuotaPeriod, now: DateTime<Utc>) -> String {
+    let allowance = match period {
+        QuotaPeriod::Daily => "daily",
+        QuotaPeriod::Monthly => "monthly",
+    };
+    let reset = format_reset(period, now);
+    match scope {
+        UsageScope::User => format!("You've used up your {allowance} allowance. It resets {reset}."),
+        UsageScope::Chat => format!("This chat has used up its {allowance} allowance. It resets {reset}."),
+    }
+}
+
+/// When the period's counters reset, e.g. `in 5h 12m` or
+/// `on 2026-11-01 (UTC)`.
+fn format_reset(period: QuotaPeriod, now: DateTime<Utc>) -> String {
+    let reset_day = period.next_reset(now.date_naive());
+    match period {
+        QuotaPeriod::Daily => {
+            let reset = reset_day.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc();
+            let minutes = (reset - now).num_minutes().max(1);
+            format!("in {}h {}m", minutes / 60, minutes % 60)
+        }
+        QuotaPeriod::Monthly => format!("on {reset_day} (UTC)"),
+    }
+}
+
+pub fn format_nova_response(response: &NovaResponse) -> String {
+    if let Some(text) = response.text.clone()
+        && !text.trim().is_empty()
+    {
+        return text;
+    }
+
     "Nova Gateway returned an empty response.".to_string()
 }
+
+/// Text shown in the draft message while a streamed answer is still arriving.
+pub fn format_stream_preview(text: &str) -> String {
+    truncate_with_suffix(text, " …")
+}
+
+/// Text left in the draft message when the stream broke off.
+pub fn format_incomplete_answer(text: &str) -> String {
+    truncate_with_suffix(text, "\n\n[The answer was cut off.]")
+}
+
+fn truncate_with_suffix(text: &str, suffix: &str) -> String {
+    let limit = TELEGRAM_MESSAGE_LIMIT - suffix.encode_utf16().count();
+    let mut truncated = String::new();
+    let mut length = 0;
+    for ch in text.chars() {
+        length += ch.len_utf16();
+        if length > limit {
+            break;
+        }
+        truncated.push(ch);
+    }
+    truncated.push_str(suffix);
+    truncated
+}
@@ -2,7 +2,8 @@ mod controller;
 pub mod dto;
 mod handler;
 pub mod helpers;
+mod rate_limit;
 
 pub use controller::BotController;
-pub use handler::{handle_command_update, handle_message_update};
+pub use handler::{handle_callback_update, handle_command_update, handle_message_update};
 pub use dto::BotCommand;
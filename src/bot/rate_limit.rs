@@ -0,0 +1,205 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    config::dto::{RateLimit, RateLimitSettings},
+    metrics::METRICS,
+};
+
+/// Buckets that are full again carry no information and are dropped once a
+/// map grows past this size.
+const PRUNE_THRESHOLD: usize = 1024;
+
+/// In-memory token buckets limiting how often users and chats may prompt
+/// Nova. A prompt needs a token from both the user's and the chat's bucket.
+pub struct RateLimiter {
+    settings: RateLimitSettings,
+    buckets: Mutex<Buckets>,
+}
+
+#[derive(Default)]
+struct Buckets {
+    users: HashMap<i64, Bucket>,
+    chats: HashMap<i64, Bucket>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(settings: RateLimitSettings) -> Self {
+        Self {
+            settings,
+            buckets: Mutex::default(),
+        }
+    }
+
+    /// Takes a token for the user and the chat, or returns how long to wait
+    /// until both have one. Nothing is taken when either bucket is empty.
+    pub fn acquire(&self, user_id: Option<i64>, chat_id: i64) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let Buckets { users, chats } = &mut *buckets;
+
+        let user = match (self.settings.per_user, user_id) {
+            (Some(limit), Some(user_id)) => Some((limit, refill(users, user_id, limit, now))),
+            _ => None,
+        };
+        let chat = self.settings.per_chat.map(|limit| (limit, refill(chats, chat_id, limit, now)));
+
+        let user_wait = user.map(|(limit, bucket)| wait_time(bucket, limit));
+        let chat_wait = chat.map(|(limit, bucket)| wait_time(bucket, limit));
+        if let Some(wait) = user_wait.filter(|wait| !wait.is_zero()) {
+            METRICS.rate_limited.with_label_values(&["user"]).inc();
+            return Err(wait.max(chat_wait.unwrap_or_default()));
+        }
+        if let Some(wait) = chat_wait.filter(|wait| !wait.is_zero()) {
+            METRICS.rate_limited.with_label_values(&["chat"]).inc();
+            return Err(wait);
+        }
+
+        if let (Some(user_id), Some(_)) = (user_id, user) {
+            take(users, user_id);
+        }
+        if chat.is_some() {
+            take(chats, chat_id);
+        }
+        Ok(())
+    }
+}
+
+/// Tops up the bucket for the time passed since it was last used and
+/// returns its state.
+fn refill(buckets: &mut HashMap<i64, Bucket>, key: i64, limit: RateLimit, now: Instant) -> Bucket {
+    let capacity = f64::from(limit.capacity);
+    if buckets.len() > PRUNE_THRESHOLD {
+        buckets.retain(|_, bucket| {
+            let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+            bucket.tokens + elapsed * limit.refill_per_second() < capacity
+        });
+    }
+
+    let bucket = buckets.entry(key).or_insert(Bucket {
+        tokens: capacity,
+        updated: now,
+    });
+    let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+    bucket.tokens = (bucket.tokens + elapsed * limit.refill_per_second()).min(capacity);
+    bucket.updated = now;
+    *bucket
+}
+
+fn wait_time(bucket: Bucket, limit: RateLimit) -> Duration {
+    if bucket.tokens >= 1.0 {
+        return Duration::ZERO;
+    }
+    Duration::from_secs_f64((1.0 - bucket.tokens) / limit.refill_per_second())
+}
+
+fn take(buckets: &mut HashMap<i64, Bucket>, key: i64) {
+    if let Some(bucket) = buckets.get_mut(&key) {
+        bucket.tokens -= 1.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limit(capacity: u32) -> RateLimit {
+        RateLimit {
+            capacity,
+            window: Duration::from_secs(60),
+        }
+    }
+
+    fn rate_limiter(per_user: Option<u32>, per_chat: Option<u32>) -> RateLimiter {
+        RateLimiter::new(RateLimitSettings {
+            per_user: per_user.map(limit),
+            per_chat: per_chat.map(limit),
+        })
+    }
+
+    #[test]
+    fn limits_each_user() {
+        let limiter = rate_limiter(Some(2), None);
+        assert!(limiter.acquire(Some(1), 10).is_ok());
+        assert!(limiter.acquire(Some(1), 11).is_ok());
+        assert!(limiter.acquire(Some(1), 10).is_err());
+        assert!(limiter.acquire(Some(2), 10).is_ok());
+        assert!(limiter.acquire(None, 10).is_ok());
+    }
+
+    #[test]
+    fn limits_each_chat() {
+        let limiter = rate_limiter(None, Some(1));
+        assert!(limiter.acquire(Some(1), 10).is_ok());
+        assert!(limiter.acquire(Some(2), 10).is_err());
+        assert!(limiter.acquire(Some(2), 11).is_ok());
+    }
+
+    #[test]
+    fn takes_nothing_when_rejected() {
+        let limiter = rate_limiter(Some(1), Some(1));
+        assert!(limiter.acquire(Some(1), 10).is_ok());
+        assert!(limiter.acquire(Some(2), 10).is_err());
+        assert!(limiter.acquire(Some(2), 11).is_ok());
+    }
+
+    #[test]
+    fn returns_the_time_until_the_next_token() {
+        let limiter = rate_limiter(Some(1), Some(2));
+        limiter.acquire(Some(1), 10).unwrap();
+        let wait = limiter.acquire(Some(1), 10).unwrap_err();
+        assert!(wait > Duration::from_secs(59) && wait <= Duration::from_secs(60), "{wait:?}");
+
+        // Half a token left at one token per 30 seconds: 15 seconds to wait
+        let limiter = rate_limiter(None, Some(2));
+        let now = Instant::now();
+        limiter.buckets.lock().unwrap().chats.insert(10, Bucket { tokens: 0.5, updated: now });
+        let wait = limiter.acquire(Some(1), 10).unwrap_err();
+        assert!(wait > Duration::from_secs(14) && wait <= Duration::from_secs(15), "{wait:?}");
+    }
+
+    #[test]
+    fn refills_over_time_up_to_capacity() {
+        let limit = limit(2);
+        let start = Instant::now();
+        let mut buckets = HashMap::new();
+        assert_eq!(refill(&mut buckets, 1, limit, start).tokens, 2.0);
+        take(&mut buckets, 1);
+        take(&mut buckets, 1);
+        assert_eq!(refill(&mut buckets, 1, limit, start).tokens, 0.0);
+
+        assert_eq!(refill(&mut buckets, 1, limit, start + Duration::from_secs(15)).tokens, 0.5);
+        assert_eq!(refill(&mut buckets, 1, limit, start + Duration::from_secs(600)).tokens, 2.0);
+    }
+
+    #[test]
+    fn prunes_full_buckets_above_the_threshold() {
+        let limit = limit(2);
+        let now = Instant::now();
+        let mut buckets: HashMap<i64, Bucket> = (0..=PRUNE_THRESHOLD as i64)
+            .map(|key| {
+                let tokens = if key % 2 == 0 { 2.0 } else { 0.0 };
+                (key, Bucket { tokens, updated: now })
+            })
+            .collect();
+
+        refill(&mut buckets, -1, limit, now);
+        assert_eq!(buckets.len(), PRUNE_THRESHOLD / 2 + 1);
+        assert!(buckets.keys().all(|key| key % 2 != 0));
+
+        // At or below the threshold nothing is pruned
+        let mut buckets: HashMap<i64, Bucket> =
+            (0..10).map(|key| (key, Bucket { tokens: 2.0, updated: now })).collect();
+        refill(&mut buckets, -1, limit, now);
+        assert_eq!(buckets.len(), 11);
+    }
+}
@@ -1,15 +1,42 @@
-use std::env;
+use std::{
+    collections::{BTreeMap, HashSet},
+    env,
+    time::Duration,
+};
 
 use dotenvy::dotenv;
+use reqwest::Url;
 use thiserror::Error;
 
-use super::dto::ReasoningSettings;
+use super::dto::{
+    AccessControl, CircuitBreakerSettings, ConversationMode, ImageMode, InstructionMode, LogFormat, ReasoningSettings, RetryPolicy, StateStoreKind,
+    QuotaLimits, QuotaSettings, RateLimit, RateLimitSettings, ThreadPolicy, TokenBounds, UpdateMode,
+};
 
 const DEFAULT_BASE_URL: &str = "https://gateway.inferenco.com";
 const DEFAULT_MODEL: &str = "gpt-5-mini";
 const DEFAULT_VERBOSITY: &str = "Medium";
 const DEFAULT_MAX_TOKENS: u32 = 1024;
+const DEFAULT_TOKEN_CAP_MIN: u32 = 256;
+const DEFAULT_TOKEN_CAP_MAX: u32 = 4096;
 const DEFAULT_TIMEOUT_SECS: u64 = 60;
+const DEFAULT_STREAM_EDIT_INTERVAL_MS: u64 = 1500;
+const DEFAULT_STATE_PATH: &str = "chat_state.json";
+const DEFAULT_PORT: u16 = 8080;
+const DEFAULT_LOG_LEVEL: &str = "info";
+const DEFAULT_RETRY_MAX_ATTEMPTS: u32 = 3;
+const DEFAULT_RETRY_BASE_DELAY_MS: u64 = 500;
+const DEFAULT_RETRY_MAX_DELAY_MS: u64 = 10_000;
+const DEFAULT_RETRY_JITTER: f64 = 0.2;
+const DEFAULT_RETRY_STATUSES: &[u16] = &[429, 500, 502, 503, 504];
+const DEFAULT_BREAKER_THRESHOLD: u32 = 5;
+const DEFAULT_BREAKER_COOLDOWN_SECS: u64 = 30;
+const DEFAULT_RATE_LIMIT_WINDOW_SECS: u64 = 60;
+/// Telegram limits callback data to 64 bytes; model buttons carry a
+/// `model:` prefix.
+const MAX_MODEL_NAME_LEN: usize = 58;
+/// Persona buttons carry a `persona:` prefix in their callback data.
+const MAX_PERSONA_NAME_LEN: usize = 56;
 
 #[derive(Debug, Clone)]
 pub struct Config {
@@ -17,10 +44,35 @@ pub struct Config {
     nova_api_key: String,
     nova_base_url: String,
     nova_model: String,
+    nova_models: Vec<String>,
     nova_verbosity: String,
     nova_max_tokens: u32,
+    token_bounds: TokenBounds,
     reasoning: ReasoningSettings,
     nova_timeout_seconds: u64,
+    nova_streaming: bool,
+    stream_edit_interval_ms: u64,
+    private_chat_mode: ConversationMode,
+    group_chat_mode: ConversationMode,
+    group_triggers: bool,
+    group_thread_policy: ThreadPolicy,
+    answer_buttons: bool,
+    image_mode: ImageMode,
+    personas: BTreeMap<String, String>,
+    access: AccessControl,
+    instruction_mode: InstructionMode,
+    state_store: StateStoreKind,
+    state_path: String,
+    update_mode: UpdateMode,
+    webhook_url: Option<Url>,
+    webhook_secret: Option<String>,
+    port: u16,
+    log_format: LogFormat,
+    log_level: String,
+    retry_policy: RetryPolicy,
+    circuit_breaker: CircuitBreakerSettings,
+    rate_limits: RateLimitSettings,
+    quotas: QuotaSettings,
 }
 
 #[derive(Debug, Error)]
@@ -31,6 +83,8 @@ pub enum ConfigError {
     InvalidNumber(&'static str, String),
     #[error("invalid boolean for {0}: {1}")]
     InvalidBoolean(&'static str, String),
+    #[error("invalid value for {0}: {1}")]
+    InvalidValue(&'static str, String),
     #[error("environment error: {0}")]
     Env(#[from] env::VarError),
 }
@@ -44,6 +98,7 @@ impl Config {
 
         let nova_base_url = env::var("NOVA_BASE_URL").unwrap_or_else(|_| DEFAULT_BASE_URL.to_string());
         let nova_model = env::var("NOVA_MODEL").unwrap_or_else(|_| DEFAULT_MODEL.to_string());
+        let nova_models = load_model_allowlist(&nova_model)?;
         let nova_verbosity = env::var("NOVA_VERBOSITY").unwrap_or_else(|_| DEFAULT_VERBOSITY.to_string());
 
         let nova_max_tokens = match env::var("NOVA_MAX_TOKENS") {
@@ -52,9 +107,10 @@ impl Config {
                 .map_err(|_| ConfigError::InvalidNumber("NOVA_MAX_TOKENS", value))?,
             Err(_) => DEFAULT_MAX_TOKENS,
         };
+        let token_bounds = load_token_bounds()?;
 
         let reasoning_enabled = match env::var("NOVA_REASONING") {
-            Ok(value) => parse_bool(&value).ok_or_else(|| ConfigError::InvalidBoolean("NOVA_REASONING", value))?,
+            Ok(value) => parse_bool(&value).ok_or(ConfigError::InvalidBoolean("NOVA_REASONING", value))?,
             Err(_) => false,
         };
 
@@ -67,18 +123,166 @@ impl Config {
             Err(_) => DEFAULT_TIMEOUT_SECS,
         };
 
+        let nova_streaming = match env::var("NOVA_STREAMING") {
+            Ok(value) => parse_bool(&value).ok_or(ConfigError::InvalidBoolean("NOVA_STREAMING", value))?,
+            Err(_) => false,
+        };
+
+        let stream_edit_interval_ms = match env::var("NOVA_STREAM_EDIT_INTERVAL_MS") {
+            Ok(value) => value
+                .parse::<u64>()
+                .map_err(|_| ConfigError::InvalidNumber("NOVA_STREAM_EDIT_INTERVAL_MS", value))?,
+            Err(_) => DEFAULT_STREAM_EDIT_INTERVAL_MS,
+        };
+
+        let private_chat_mode = load_conversation_mode("BOT_PRIVATE_CHAT_MODE", ConversationMode::Conversation)?;
+        let group_chat_mode = load_conversation_mode("BOT_GROUP_CHAT_MODE", ConversationMode::Command)?;
+
+        let group_triggers = match env::var("BOT_GROUP_TRIGGERS") {
+            Ok(value) => parse_bool(&value).ok_or(ConfigError::InvalidBoolean("BOT_GROUP_TRIGGERS", value))?,
+            Err(_) => true,
+        };
+
+        let group_thread_policy = match env::var("BOT_GROUP_THREAD_POLICY") {
+            Ok(value) => {
+                ThreadPolicy::parse(&value).ok_or(ConfigError::InvalidValue("BOT_GROUP_THREAD_POLICY", value))?
+            }
+            Err(_) => ThreadPolicy::Shared,
+        };
+
+        let answer_buttons = match env::var("BOT_ANSWER_BUTTONS") {
+            Ok(value) => parse_bool(&value).ok_or(ConfigError::InvalidBoolean("BOT_ANSWER_BUTTONS", value))?,
+            Err(_) => true,
+        };
+
+        let image_mode = match env::var("NOVA_IMAGE_MODE") {
+            Ok(value) => ImageMode::parse(&value).ok_or(ConfigError::InvalidValue("NOVA_IMAGE_MODE", value))?,
+            Err(_) => ImageMode::DataUrl,
+        };
+
+        let personas = load_personas()?;
+        let access = AccessControl {
+            admins: load_id_list("BOT_ADMINS")?,
+            allowed_users: load_id_list("BOT_ALLOWED_USERS")?,
+            allowed_chats: load_id_list("BOT_ALLOWED_CHATS")?,
+            denied_users: load_id_list("BOT_DENIED_USERS")?,
+            denied_chats: load_id_list("BOT_DENIED_CHATS")?,
+        };
+        let instruction_mode = match env::var("NOVA_INSTRUCTION_MODE") {
+            Ok(value) => {
+                InstructionMode::parse(&value).ok_or(ConfigError::InvalidValue("NOVA_INSTRUCTION_MODE", value))?
+            }
+            Err(_) => InstructionMode::Field,
+        };
+
+        let state_store = match env::var("CHAT_STATE_STORE") {
+            Ok(value) => StateStoreKind::parse(&value).ok_or(ConfigError::InvalidValue("CHAT_STATE_STORE", value))?,
+            Err(_) => StateStoreKind::Memory,
+        };
+        let state_path = env::var("CHAT_STATE_PATH").unwrap_or_else(|_| DEFAULT_STATE_PATH.to_string());
+
+        let update_mode = match env::var("BOT_UPDATE_MODE") {
+            Ok(value) => UpdateMode::parse(&value).ok_or(ConfigError::InvalidValue("BOT_UPDATE_MODE", value))?,
+            Err(_) => UpdateMode::Polling,
+        };
+
+        let webhook_url = match env::var("TELEGRAM_WEBHOOK_URL") {
+            Ok(value) => Some(Url::parse(&value).map_err(|_| ConfigError::InvalidValue("TELEGRAM_WEBHOOK_URL", value))?),
+            Err(_) if update_mode == UpdateMode::Webhook => return Err(ConfigError::MissingVar("TELEGRAM_WEBHOOK_URL")),
+            Err(_) => None,
+        };
+
+        let webhook_secret = match env::var("TELEGRAM_WEBHOOK_SECRET") {
+            Ok(value) if is_valid_webhook_secret(&value) => Some(value),
+            Ok(_) => {
+                let hint = "expected 1-256 characters from A-Z, a-z, 0-9, _ and -".to_string();
+                return Err(ConfigError::InvalidValue("TELEGRAM_WEBHOOK_SECRET", hint));
+            }
+            Err(_) => None,
+        };
+
+        let port = match env::var("PORT") {
+            Ok(value) => value
+                .parse::<u16>()
+                .map_err(|_| ConfigError::InvalidNumber("PORT", value))?,
+            Err(_) => DEFAULT_PORT,
+        };
+
+        let log_format = match env::var("LOG_FORMAT") {
+            Ok(value) => LogFormat::parse(&value).ok_or(ConfigError::InvalidValue("LOG_FORMAT", value))?,
+            Err(_) => LogFormat::Pretty,
+        };
+        let log_level = env::var("LOG_LEVEL").unwrap_or_else(|_| DEFAULT_LOG_LEVEL.to_string());
+
+        let retry_policy = load_retry_policy()?;
+
+        let breaker_threshold = match env::var("NOVA_BREAKER_THRESHOLD") {
+            Ok(value) => value
+                .parse::<u32>()
+                .map_err(|_| ConfigError::InvalidNumber("NOVA_BREAKER_THRESHOLD", value))?,
+            Err(_) => DEFAULT_BREAKER_THRESHOLD,
+        };
+
+        let breaker_cooldown_secs = match env::var("NOVA_BREAKER_COOLDOWN_SECONDS") {
+            Ok(value) => value
+                .parse::<u64>()
+                .map_err(|_| ConfigError::InvalidNumber("NOVA_BREAKER_COOLDOWN_SECONDS", value))?,
+            Err(_) => DEFAULT_BREAKER_COOLDOWN_SECS,
+        };
+
         Ok(Self {
             telegram_bot_token,
             nova_api_key,
             nova_base_url,
             nova_model,
+            nova_models,
             nova_verbosity,
             nova_max_tokens,
+            token_bounds,
             reasoning: ReasoningSettings {
                 enabled: reasoning_enabled,
                 effort: reasoning_effort,
             },
             nova_timeout_seconds,
+            nova_streaming,
+            stream_edit_interval_ms,
+            private_chat_mode,
+            group_chat_mode,
+            group_triggers,
+            group_thread_policy,
+            answer_buttons,
+            image_mode,
+            personas,
+            access,
+            instruction_mode,
+            state_store,
+            state_path,
+            update_mode,
+            webhook_url,
+            webhook_secret,
+            port,
+            log_format,
+            log_level,
+            retry_policy,
+            circuit_breaker: CircuitBreakerSettings {
+                failure_threshold: breaker_threshold,
+                cooldown: Duration::from_secs(breaker_cooldown_secs),
+            },
+            rate_limits: load_rate_limits()?,
+            quotas: QuotaSettings {
+                per_user: QuotaLimits {
+                    daily_prompts: load_quota_limit("BOT_USER_DAILY_PROMPTS")?,
+                    daily_tokens: load_quota_limit("BOT_USER_DAILY_TOKENS")?,
+                    monthly_prompts: load_quota_limit("BOT_USER_MONTHLY_PROMPTS")?,
+                    monthly_tokens: load_quota_limit("BOT_USER_MONTHLY_TOKENS")?,
+                },
+                per_chat: QuotaLimits {
+                    daily_prompts: load_quota_limit("BOT_CHAT_DAILY_PROMPTS")?,
+                    daily_tokens: load_quota_limit("BOT_CHAT_DAILY_TOKENS")?,
+                    monthly_prompts: load_quota_limit("BOT_CHAT_MONTHLY_PROMPTS")?,
+                    monthly_tokens: load_quota_limit("BOT_CHAT_MONTHLY_TOKENS")?,
+                },
+            },
         })
     }
 
@@ -98,6 +302,12 @@ impl Config {
         &self.nova_model
     }
 
+    /// Models chats may switch to with `/model`; always contains the default
+    /// model first.
+    pub fn nova_models(&self) -> &[String] {
+        &self.nova_models
+    }
+
     pub fn nova_verbosity(&self) -> &str {
         &self.nova_verbosity
     }
@@ -106,6 +316,10 @@ impl Config {
         self.nova_max_tokens
     }
 
+    pub fn token_bounds(&self) -> TokenBounds {
+        self.token_bounds
+    }
+
     pub fn reasoning(&self) -> &ReasoningSettings {
         &self.reasoning
     }
@@ -113,6 +327,99 @@ impl Config {
     pub fn nova_timeout_seconds(&self) -> u64 {
         self.nova_timeout_seconds
     }
+
+    pub fn nova_streaming(&self) -> bool {
+        self.nova_streaming
+    }
+
+    pub fn stream_edit_interval_ms(&self) -> u64 {
+        self.stream_edit_interval_ms
+    }
+
+    pub fn private_chat_mode(&self) -> ConversationMode {
+        self.private_chat_mode
+    }
+
+    pub fn group_chat_mode(&self) -> ConversationMode {
+        self.group_chat_mode
+    }
+
+    pub fn group_triggers(&self) -> bool {
+        self.group_triggers
+    }
+
+    pub fn answer_buttons(&self) -> bool {
+        self.answer_buttons
+    }
+
+    pub fn group_thread_policy(&self) -> ThreadPolicy {
+        self.group_thread_policy
+    }
+
+    pub fn image_mode(&self) -> ImageMode {
+        self.image_mode
+    }
+
+    /// Persona presets chats can pick with `/persona`, by name.
+    pub fn personas(&self) -> &BTreeMap<String, String> {
+        &self.personas
+    }
+
+    pub fn access(&self) -> &AccessControl {
+        &self.access
+    }
+
+    pub fn instruction_mode(&self) -> InstructionMode {
+        self.instruction_mode
+    }
+
+    pub fn state_store(&self) -> StateStoreKind {
+        self.state_store
+    }
+
+    pub fn state_path(&self) -> &str {
+        &self.state_path
+    }
+
+    pub fn update_mode(&self) -> UpdateMode {
+        self.update_mode
+    }
+
+    pub fn webhook_url(&self) -> Option<&Url> {
+        self.webhook_url.as_ref()
+    }
+
+    pub fn webhook_secret(&self) -> Option<&str> {
+        self.webhook_secret.as_deref()
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn log_format(&self) -> LogFormat {
+        self.log_format
+    }
+
+    pub fn log_level(&self) -> &str {
+        &self.log_level
+    }
+
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry_policy
+    }
+
+    pub fn circuit_breaker(&self) -> &CircuitBreakerSettings {
+        &self.circuit_breaker
+    }
+
+    pub fn rate_limits(&self) -> RateLimitSettings {
+        self.rate_limits
+    }
+
+    pub fn quotas(&self) -> QuotaSettings {
+        self.quotas
+    }
 }
 
 fn load_required(key: &'static str) -> Result<String, ConfigError> {
@@ -122,6 +429,184 @@ fn load_required(key: &'static str) -> Result<String, ConfigError> {
     })
 }
 
+fn load_retry_policy() -> Result<RetryPolicy, ConfigError> {
+    let max_attempts = match env::var("NOVA_RETRY_MAX_ATTEMPTS") {
+        Ok(value) => value
+            .parse::<u32>()
+            .ok()
+            .filter(|attempts| *attempts >= 1)
+            .ok_or(ConfigError::InvalidNumber("NOVA_RETRY_MAX_ATTEMPTS", value))?,
+        Err(_) => DEFAULT_RETRY_MAX_ATTEMPTS,
+    };
+
+    let base_delay_ms = match env::var("NOVA_RETRY_BASE_DELAY_MS") {
+        Ok(value) => value
+            .parse::<u64>()
+            .map_err(|_| ConfigError::InvalidNumber("NOVA_RETRY_BASE_DELAY_MS", value))?,
+        Err(_) => DEFAULT_RETRY_BASE_DELAY_MS,
+    };
+
+    let max_delay_ms = match env::var("NOVA_RETRY_MAX_DELAY_MS") {
+        Ok(value) => value
+            .parse::<u64>()
+            .map_err(|_| ConfigError::InvalidNumber("NOVA_RETRY_MAX_DELAY_MS", value))?,
+        Err(_) => DEFAULT_RETRY_MAX_DELAY_MS,
+    };
+
+    let jitter = match env::var("NOVA_RETRY_JITTER") {
+        Ok(value) => value
+            .parse::<f64>()
+            .ok()
+            .filter(|jitter| (0.0..=1.0).contains(jitter))
+            .ok_or(ConfigError::InvalidNumber("NOVA_RETRY_JITTER", value))?,
+        Err(_) => DEFAULT_RETRY_JITTER,
+    };
+
+    let retryable_statuses = match env::var("NOVA_RETRY_STATUSES") {
+        Ok(value) => value
+            .split(',')
+            .map(str::trim)
+            .filter(|status| !status.is_empty())
+            .map(|status| status.parse::<u16>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| ConfigError::InvalidNumber("NOVA_RETRY_STATUSES", value))?,
+        Err(_) => DEFAULT_RETRY_STATUSES.to_vec(),
+    };
+
+    Ok(RetryPolicy {
+        max_attempts,
+        base_delay: Duration::from_millis(base_delay_ms),
+        max_delay: Duration::from_millis(max_delay_ms),
+        jitter,
+        retryable_statuses,
+    })
+}
+
+fn load_token_bounds() -> Result<TokenBounds, ConfigError> {
+    let min = match env::var("NOVA_TOKEN_CAP_MIN") {
+        Ok(value) => value
+            .parse::<u32>()
+            .map_err(|_| ConfigError::InvalidNumber("NOVA_TOKEN_CAP_MIN", value))?,
+        Err(_) => DEFAULT_TOKEN_CAP_MIN,
+    };
+    let max = match env::var("NOVA_TOKEN_CAP_MAX") {
+        Ok(value) => value
+            .parse::<u32>()
+            .map_err(|_| ConfigError::InvalidNumber("NOVA_TOKEN_CAP_MAX", value))?,
+        Err(_) => DEFAULT_TOKEN_CAP_MAX,
+    };
+
+    if min == 0 || min > max {
+        return Err(ConfigError::InvalidValue("NOVA_TOKEN_CAP_MIN", min.to_string()));
+    }
+    Ok(TokenBounds { min, max })
+}
+
+/// Reads the per-user and per-chat prompt limits; a limit of `0` (the
+/// default) disables it.
+fn load_rate_limits() -> Result<RateLimitSettings, ConfigError> {
+    let window_secs = match env::var("BOT_RATE_LIMIT_WINDOW_SECONDS") {
+        Ok(value) => value
+            .parse::<u64>()
+            .ok()
+            .filter(|secs| *secs > 0)
+            .ok_or(ConfigError::InvalidNumber("BOT_RATE_LIMIT_WINDOW_SECONDS", value))?,
+        Err(_) => DEFAULT_RATE_LIMIT_WINDOW_SECS,
+    };
+    let window = Duration::from_secs(window_secs);
+
+    let limit = |key: &'static str| -> Result<Option<RateLimit>, ConfigError> {
+        let capacity = match env::var(key) {
+            Ok(value) => value.parse::<u32>().map_err(|_| ConfigError::InvalidNumber(key, value))?,
+            Err(_) => 0,
+        };
+        Ok((capacity > 0).then_some(RateLimit { capacity, window }))
+    };
+
+    Ok(RateLimitSettings {
+        per_user: limit("BOT_USER_RATE_LIMIT")?,
+        per_chat: limit("BOT_CHAT_RATE_LIMIT")?,
+    })
+}
+
+/// Reads a usage cap; `0` or unset means unlimited.
+fn load_quota_limit(key: &'static str) -> Result<Option<u64>, ConfigError> {
+    match env::var(key) {
+        Ok(value) => {
+            let limit = value.parse::<u64>().map_err(|_| ConfigError::InvalidNumber(key, value))?;
+            Ok((limit > 0).then_some(limit))
+        }
+        Err(_) => Ok(None),
+    }
+}
+
+/// Reads a comma-separated list of Telegram user or chat IDs.
+fn load_id_list(key: &'static str) -> Result<HashSet<i64>, ConfigError> {
+    let Ok(value) = env::var(key) else {
+        return Ok(HashSet::new());
+    };
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(|id| id.parse::<i64>().map_err(|_| ConfigError::InvalidNumber(key, id.to_string())))
+        .collect()
+}
+
+/// Reads persona presets from `BOT_PERSONAS`, a JSON object mapping preset
+/// names to their system prompts.
+fn load_personas() -> Result<BTreeMap<String, String>, ConfigError> {
+    let Ok(value) = env::var("BOT_PERSONAS") else {
+        return Ok(BTreeMap::new());
+    };
+    let personas: BTreeMap<String, String> = match serde_json::from_str(&value) {
+        Ok(personas) => personas,
+        Err(_) => return Err(ConfigError::InvalidValue("BOT_PERSONAS", value)),
+    };
+
+    let invalid_name = personas
+        .keys()
+        .find(|name| name.is_empty() || name.len() > MAX_PERSONA_NAME_LEN || name.contains(char::is_whitespace));
+    if let Some(name) = invalid_name {
+        return Err(ConfigError::InvalidValue("BOT_PERSONAS", name.clone()));
+    }
+    Ok(personas)
+}
+
+fn load_model_allowlist(default_model: &str) -> Result<Vec<String>, ConfigError> {
+    // The default model gets a button in `/model` like any other
+    if default_model.len() > MAX_MODEL_NAME_LEN {
+        return Err(ConfigError::InvalidValue("NOVA_MODEL", default_model.to_string()));
+    }
+    let mut models = vec![default_model.to_string()];
+    if let Ok(value) = env::var("NOVA_MODELS") {
+        for model in value.split(',').map(str::trim).filter(|model| !model.is_empty()) {
+            if model.len() > MAX_MODEL_NAME_LEN {
+                return Err(ConfigError::InvalidValue("NOVA_MODELS", model.to_string()));
+            }
+            if !models.iter().any(|known| known == model) {
+                models.push(model.to_string());
+            }
+        }
+    }
+    Ok(models)
+}
+
+fn load_conversation_mode(key: &'static str, default: ConversationMode) -> Result<ConversationMode, ConfigError> {
+    match env::var(key) {
+        Ok(value) => ConversationMode::parse(&value).ok_or(ConfigError::InvalidValue(key, value)),
+        Err(_) => Ok(default),
+    }
+}
+
+/// Telegram accepts 1-256 characters from `A-Z`, `a-z`, `0-9`, `_` and `-`.
+fn is_valid_webhook_secret(value: &str) -> bool {
+    (1..=256).contains(&value.len())
+        && value
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'-')
+}
+
 fn parse_bool(value: &str) -> Option<bool> {
     match value.to_lowercase().as_str() {
         "true" | "1" | "yes" | "y" => Some(true),
@@ -1,5 +1,296 @@
+use std::{collections::HashSet, time::Duration};
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
 #[derive(Debug, Clone, Default)]
 pub struct ReasoningSettings {
     pub enabled: bool,
     pub effort: Option<String>,
 }
+
+/// Range chat admins may set the per-chat token cap to.
+#[derive(Debug, Clone, Copy)]
+pub struct TokenBounds {
+    pub min: u32,
+    pub max: u32,
+}
+
+impl TokenBounds {
+    pub fn contains(&self, tokens: u32) -> bool {
+        (self.min..=self.max).contains(&tokens)
+    }
+
+    pub fn clamp(&self, tokens: u32) -> u32 {
+        tokens.clamp(self.min, self.max)
+    }
+}
+
+/// Telegram user and chat IDs from the environment that control who may use
+/// the bot. Chats and users granted or revoked at runtime are kept in the
+/// state store and combined with these.
+#[derive(Debug, Clone, Default)]
+pub struct AccessControl {
+    /// Users who can always use the bot and manage access with `/grant`
+    /// and `/revoke`.
+    pub admins: HashSet<i64>,
+    pub allowed_users: HashSet<i64>,
+    pub allowed_chats: HashSet<i64>,
+    pub denied_users: HashSet<i64>,
+    pub denied_chats: HashSet<i64>,
+}
+
+/// Token bucket allowing `capacity` requests at once, refilled evenly so
+/// that `capacity` more are allowed per `window`.
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+    pub capacity: u32,
+    pub window: Duration,
+}
+
+impl RateLimit {
+    pub fn refill_per_second(&self) -> f64 {
+        f64::from(self.capacity) / self.window.as_secs_f64()
+    }
+}
+
+/// Limits on prompts sent to Nova; `None` disables a limit.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RateLimitSettings {
+    pub per_user: Option<RateLimit>,
+    pub per_chat: Option<RateLimit>,
+}
+
+/// Usage caps for one user or chat; `None` means unlimited.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct QuotaLimits {
+    pub daily_prompts: Option<u64>,
+    pub daily_tokens: Option<u64>,
+    pub monthly_prompts: Option<u64>,
+    pub monthly_tokens: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct QuotaSettings {
+    pub per_user: QuotaLimits,
+    pub per_chat: QuotaLimits,
+}
+
+/// When and how often failed Nova gateway calls are retried.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one.
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// Fraction of the delay that is randomly added or subtracted, `0.0..=1.0`.
+    pub jitter: f64,
+    pub retryable_statuses: Vec<u16>,
+}
+
+impl RetryPolicy {
+    pub fn is_retryable_status(&self, status: u16) -> bool {
+        self.retryable_statuses.contains(&status)
+    }
+
+    /// Delay before retrying after the given (1-based) failed attempt:
+    /// exponential backoff from `base_delay`, capped at `max_delay`, with
+    /// jitter applied.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(31);
+        let delay = self.base_delay.saturating_mul(1 << exponent).min(self.max_delay);
+        if self.jitter <= 0.0 {
+            return delay;
+        }
+        let factor = 1.0 + rand::thread_rng().gen_range(-self.jitter..=self.jitter);
+        delay.mul_f64(factor.max(0.0)).min(self.max_delay)
+    }
+}
+
+/// When calls to the Nova gateway are short-circuited after repeated
+/// failures. A `failure_threshold` of `0` disables the breaker.
+#[derive(Debug, Clone)]
+pub struct CircuitBreakerSettings {
+    pub failure_threshold: u32,
+    pub cooldown: Duration,
+}
+
+/// How the bot treats plain (non-command) text in a chat.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConversationMode {
+    /// Only `/chat` is forwarded to Nova.
+    Command,
+    /// Every plain text message is forwarded to Nova as if sent with `/chat`.
+    Conversation,
+}
+
+impl ConversationMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "command" | "commands" => Some(Self::Command),
+            "conversation" | "chat" => Some(Self::Conversation),
+            _ => None,
+        }
+    }
+}
+
+/// Which messages in a group share one Nova conversation history.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThreadPolicy {
+    /// Everyone in the chat shares one conversation.
+    Shared,
+    /// Every user has their own conversation within the chat.
+    PerUser,
+    /// Every chain of replies is its own conversation.
+    PerReply,
+}
+
+impl ThreadPolicy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "shared" | "chat" => Some(Self::Shared),
+            "per_user" | "user" => Some(Self::PerUser),
+            "per_reply" | "reply" => Some(Self::PerReply),
+            _ => None,
+        }
+    }
+}
+
+/// How photos are handed to the gateway in `image_urls`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageMode {
+    /// The photo is downloaded and inlined as a base64 `data:` URL.
+    DataUrl,
+    /// The Telegram file download URL is passed through. The URL embeds the
+    /// bot token, so only use this with a gateway you trust with it.
+    FileUrl,
+}
+
+impl ImageMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "data_url" | "data" => Some(Self::DataUrl),
+            "file_url" | "url" => Some(Self::FileUrl),
+            _ => None,
+        }
+    }
+}
+
+/// How a chat's persona is handed to the gateway.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstructionMode {
+    /// Sent in the request's `instructions` field.
+    Field,
+    /// Prepended to the user's message, for gateways without an
+    /// instruction field.
+    Prepend,
+}
+
+impl InstructionMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "field" => Some(Self::Field),
+            "prepend" => Some(Self::Prepend),
+            _ => None,
+        }
+    }
+}
+
+/// Where per-chat state is kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateStoreKind {
+    Memory,
+    JsonFile,
+}
+
+impl StateStoreKind {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "memory" => Some(Self::Memory),
+            "file" | "json" => Some(Self::JsonFile),
+            _ => None,
+        }
+    }
+}
+
+/// How the bot receives updates from Telegram.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpdateMode {
+    Polling,
+    Webhook,
+}
+
+impl UpdateMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "polling" => Some(Self::Polling),
+            "webhook" => Some(Self::Webhook),
+            _ => None,
+        }
+    }
+}
+
+/// Output format of the structured logs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Pretty,
+    Json,
+}
+
+impl LogFormat {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "pretty" | "text" => Some(Self::Pretty),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(jitter: f64) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+            jitter,
+            retryable_statuses: vec![502, 503],
+        }
+    }
+
+    #[test]
+    fn doubles_the_delay_after_each_attempt() {
+        let policy = policy(0.0);
+        let delays: Vec<u128> = (1..=4).map(|attempt| policy.backoff(attempt).as_millis()).collect();
+        assert_eq!(delays, [100, 200, 400, 800]);
+    }
+
+    #[test]
+    fn caps_the_delay_at_max_delay() {
+        let policy = policy(0.0);
+        assert_eq!(policy.backoff(5), Duration::from_secs(1));
+        assert_eq!(policy.backoff(u32::MAX), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn keeps_jittered_delays_in_range_and_under_the_cap() {
+        let policy = policy(0.5);
+        for _ in 0..100 {
+            let delay = policy.backoff(2);
+            assert!(delay >= Duration::from_millis(100) && delay <= Duration::from_millis(300), "{delay:?}");
+            assert!(policy.backoff(4) <= Duration::from_secs(1));
+            assert!(policy.backoff(10) <= Duration::from_secs(1));
+        }
+    }
+
+    #[test]
+    fn matches_retryable_statuses() {
+        let policy = policy(0.0);
+        assert!(policy.is_retryable_status(503));
+        assert!(!policy.is_retryable_status(500));
+    }
+}
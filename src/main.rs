@@ -1,6 +1,11 @@
 mod bot;
 mod config;
+mod markdown;
+mod metrics;
 mod nova;
+mod server;
+mod store;
+mod telemetry;
 mod utils;
 
 use std::sync::Arc;
@@ -10,19 +15,22 @@ type DynError = Box<dyn std::error::Error + Send + Sync>;
 use teloxide::{
     dptree,
     dispatching::{HandlerExt, UpdateFilterExt},
+    error_handlers::LoggingErrorHandler,
     prelude::Requester,
     types::Update,
     utils::command::BotCommands,
     Bot,
 };
 
-use bot::{handle_command_update, handle_message_update, BotCommand, BotController};
-use config::Config;
+use bot::{handle_callback_update, handle_command_update, handle_message_update, BotCommand, BotController};
+use config::{dto::UpdateMode, Config};
 use nova::NovaClient;
+use server::Health;
 
 #[tokio::main]
 async fn main() -> Result<(), DynError> {
     let config = Config::from_env()?;
+    telemetry::init(&config);
 
     let bot = Bot::new(config.telegram_bot_token().to_string());
     bot.set_my_commands(BotCommand::bot_commands()).await?;
@@ -31,9 +39,26 @@ async fn main() -> Result<(), DynError> {
         config.nova_api_key().to_string(),
         config.nova_base_url().to_string(),
         config.nova_timeout_seconds(),
+        config.retry_policy().clone(),
+        config.circuit_breaker().clone(),
     )?;
 
-    let controller = Arc::new(BotController::new(bot.clone(), nova_client, config));
+    let store = store::open(&config).await?;
+    let health = Arc::new(Health::new());
+    let routes = server::health_router(health.clone(), bot.clone(), nova_client.clone())
+        .merge(server::metrics_router(store.clone()));
+    let update_mode = config.update_mode();
+    let listener = match update_mode {
+        UpdateMode::Webhook => {
+            Some(server::webhook_listener(bot.clone(), &config, health.clone(), routes).await?)
+        }
+        UpdateMode::Polling => {
+            server::serve(&config, routes)?;
+            None
+        }
+    };
+
+    let controller = Arc::new(BotController::new(bot.clone(), nova_client, store, config));
 
     let handler = dptree::entry()
         .branch(
@@ -41,14 +66,29 @@ async fn main() -> Result<(), DynError> {
                 .filter_command::<BotCommand>()
                 .endpoint(handle_command_update),
         )
-        .branch(Update::filter_message().endpoint(handle_message_update));
+        .branch(Update::filter_message().endpoint(handle_message_update))
+        .branch(Update::filter_callback_query().endpoint(handle_callback_update));
 
-    let mut dispatcher = teloxide::dispatching::Dispatcher::builder(bot, handler)
+    let mut dispatcher = teloxide::dispatching::Dispatcher::builder(bot.clone(), handler)
         .dependencies(dptree::deps![controller])
         .enable_ctrlc_handler()
         .build();
 
-    dispatcher.dispatch().await;
+    tracing::info!(mode = ?update_mode, "dispatcher started");
+    health.set_dispatcher_running(true);
+    match listener {
+        Some(listener) => {
+            let error_handler = LoggingErrorHandler::with_custom_text("An error from the webhook listener");
+            dispatcher.dispatch_with_listener(listener, error_handler).await;
+        }
+        None => {
+            let listener = server::polling_listener(bot, health.clone()).await?;
+            let error_handler = LoggingErrorHandler::with_custom_text("An error from the update listener");
+            dispatcher.dispatch_with_listener(listener, error_handler).await;
+        }
+    }
+    health.set_dispatcher_running(false);
+    tracing::info!("dispatcher stopped");
 
     Ok(())
 }
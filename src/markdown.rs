@@ -0,0 +1,182 @@
+use pulldown_cmark::{CodeBlockKind, Event, Options, Parser, Tag, TagEnd};
+
+/// Converts the CommonMark produced by the model into the HTML subset
+/// accepted by Telegram's `HTML` parse mode. Constructs Telegram cannot
+/// display (headings, lists, rules, images) are rendered as plain text.
+pub fn to_telegram_html(markdown: &str) -> String {
+    let mut html = String::with_capacity(markdown.len());
+    let mut lists: Vec<List> = Vec::new();
+
+    for event in Parser::new_ext(markdown, Options::ENABLE_STRIKETHROUGH) {
+        match event {
+            Event::Start(tag) => match tag {
+                Tag::Paragraph => {
+                    // Later paragraphs of a loose list item line up with its text.
+                    if let Some(list) = lists.last()
+                        && html.ends_with('\n')
+                    {
+                        html.push_str(&" ".repeat(list.indent));
+                    }
+                }
+                Tag::HtmlBlock => {}
+                Tag::Heading { .. } | Tag::Strong => html.push_str("<b>"),
+                Tag::Emphasis => html.push_str("<i>"),
+                Tag::Strikethrough => html.push_str("<s>"),
+                Tag::BlockQuote(_) => html.push_str("<blockquote>"),
+                Tag::CodeBlock(kind) => {
+                    // A code block inside a list item goes on its own line,
+                    // indented like the item's text.
+                    if let Some(list) = lists.last() {
+                        if !html.ends_with('\n') {
+                            html.push('\n');
+                        }
+                        html.push_str(&" ".repeat(list.indent));
+                    }
+                    match kind {
+                        CodeBlockKind::Fenced(language) if !language.is_empty() => {
+                            let language = language.split_whitespace().next().unwrap_or_default();
+                            html.push_str(&format!("<pre><code class=\"language-{}\">", escape(language)));
+                        }
+                        _ => html.push_str("<pre><code>"),
+                    }
+                }
+                Tag::List(start) => {
+                    if !lists.is_empty() && !html.ends_with('\n') {
+                        html.push('\n');
+                    }
+                    lists.push(List {
+                        next_number: start,
+                        indent: 0,
+                    });
+                }
+                Tag::Item => {
+                    let depth = lists.len().saturating_sub(1);
+                    if let Some(list) = lists.last_mut() {
+                        let marker = match list.next_number.as_mut() {
+                            Some(number) => {
+                                let marker = format!("{number}. ");
+                                *number += 1;
+                                marker
+                            }
+                            None => "• ".to_string(),
+                        };
+                        list.indent = 2 * depth + marker.chars().count();
+                        html.push_str(&"  ".repeat(depth));
+                        html.push_str(&marker);
+                    }
+                }
+                Tag::Link { dest_url, .. } => {
+                    html.push_str(&format!("<a href=\"{}\">", escape(&dest_url)));
+                }
+                _ => {}
+            },
+            Event::End(tag) => match tag {
+                TagEnd::Paragraph => html.push_str(if lists.is_empty() { "\n\n" } else { "\n" }),
+                TagEnd::Heading(_) => html.push_str("</b>\n\n"),
+                TagEnd::Strong => html.push_str("</b>"),
+                TagEnd::Emphasis => html.push_str("</i>"),
+                TagEnd::Strikethrough => html.push_str("</s>"),
+                TagEnd::BlockQuote(_) => {
+                    trim_trailing_newlines(&mut html);
+                    html.push_str("</blockquote>\n\n");
+                }
+                TagEnd::CodeBlock => {
+                    trim_trailing_newlines(&mut html);
+                    html.push_str(if lists.is_empty() { "</code></pre>\n\n" } else { "</code></pre>\n" });
+                }
+                TagEnd::List(_) => {
+                    lists.pop();
+                    if lists.is_empty() {
+                        html.push('\n');
+                    }
+                }
+                TagEnd::Item if !html.ends_with('\n') => html.push('\n'),
+                TagEnd::Link => html.push_str("</a>"),
+                _ => {}
+            },
+            Event::Text(text) | Event::Html(text) | Event::InlineHtml(text) => html.push_str(&escape(&text)),
+            Event::Code(code) => html.push_str(&format!("<code>{}</code>", escape(&code))),
+            Event::SoftBreak | Event::HardBreak => html.push('\n'),
+            Event::Rule => html.push_str("——————\n\n"),
+            _ => {}
+        }
+    }
+
+    html.trim_end().to_string()
+}
+
+/// An open list and the indentation of its current item's text.
+struct List {
+    /// `Some(next number)` for ordered lists.
+    next_number: Option<u64>,
+    indent: usize,
+}
+
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn trim_trailing_newlines(html: &mut String) {
+    while html.ends_with('\n') {
+        html.pop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_headings_in_bold() {
+        assert_eq!(to_telegram_html("# Title\n\nBody"), "<b>Title</b>\n\nBody");
+    }
+
+    #[test]
+    fn renders_nested_and_ordered_lists() {
+        assert_eq!(
+            to_telegram_html("- one\n  - nested\n- two\n\n3. three\n4. four"),
+            "• one\n  • nested\n• two\n\n3. three\n4. four"
+        );
+    }
+
+    #[test]
+    fn indents_later_paragraphs_of_loose_list_items() {
+        assert_eq!(
+            to_telegram_html("1. one\n2. two\n\n   para in two\n3. three"),
+            "1. one\n2. two\n   para in two\n3. three"
+        );
+    }
+
+    #[test]
+    fn renders_code_blocks_with_and_without_language() {
+        assert_eq!(
+            to_telegram_html("```rust title\nlet x = 1;\n```"),
+            "<pre><code class=\"language-rust\">let x = 1;</code></pre>"
+        );
+        assert_eq!(to_telegram_html("```\nplain\n```\n\nafter"), "<pre><code>plain</code></pre>\n\nafter");
+    }
+
+    #[test]
+    fn puts_code_blocks_in_list_items_on_their_own_line() {
+        assert_eq!(
+            to_telegram_html("- item\n  ```rust\n  let x = 1;\n  ```\n- next"),
+            "• item\n  <pre><code class=\"language-rust\">let x = 1;</code></pre>\n• next"
+        );
+    }
+
+    #[test]
+    fn escapes_link_targets() {
+        assert_eq!(
+            to_telegram_html("[docs](https://example.com/?a=1&b=\"2\")"),
+            "<a href=\"https://example.com/?a=1&amp;b=&quot;2&quot;\">docs</a>"
+        );
+    }
+
+    #[test]
+    fn escapes_html_in_text_and_code() {
+        assert_eq!(
+            to_telegram_html("<b>bold</b> & `a < b`\n\n```\n<tag>\n```"),
+            "&lt;b&gt;bold&lt;/b&gt; &amp; <code>a &lt; b</code>\n\n<pre><code>&lt;tag&gt;</code></pre>"
+        );
+    }
+}
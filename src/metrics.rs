@@ -0,0 +1,120 @@
+use std::sync::LazyLock;
+
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+
+/// Process-wide Prometheus metrics, exposed on `/metrics`.
+pub struct Metrics {
+    registry: Registry,
+    pub commands: IntCounterVec,
+    pub callbacks: IntCounterVec,
+    pub rate_limited: IntCounterVec,
+    pub nova_request_duration: HistogramVec,
+    pub nova_responses: IntCounterVec,
+    pub nova_rate_limit_retries: IntCounter,
+    pub telegram_failures: IntCounter,
+    pub active_chats: IntGauge,
+    pub nova_circuit_state: IntGauge,
+}
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new();
+
+        let commands = IntCounterVec::new(
+            Opts::new("bot_commands_total", "Bot commands handled, by command"),
+            &["command"],
+        )
+        .expect("valid metric");
+        let callbacks = IntCounterVec::new(
+            Opts::new("bot_callbacks_total", "Inline keyboard buttons handled, by action"),
+            &["action"],
+        )
+        .expect("valid metric");
+        let rate_limited = IntCounterVec::new(
+            Opts::new("bot_rate_limited_total", "Prompts rejected by the rate limiter, by exhausted limit"),
+            &["scope"],
+        )
+        .expect("valid metric");
+        let nova_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "nova_request_duration_seconds",
+                "Nova gateway latency including retries and reading the answer, by operation",
+            )
+                .buckets(vec![0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 20.0, 30.0, 60.0, 120.0]),
+            &["operation"],
+        )
+        .expect("valid metric");
+        let nova_responses = IntCounterVec::new(
+            Opts::new(
+                "nova_responses_total",
+                "Nova gateway responses, by operation and HTTP status (`error` when no response was received)",
+            ),
+            &["operation", "status"],
+        )
+        .expect("valid metric");
+        let nova_rate_limit_retries = IntCounter::new(
+            "nova_rate_limit_retries_total",
+            "Nova gateway requests retried after a 429 response",
+        )
+        .expect("valid metric");
+        let telegram_failures = IntCounter::new(
+            "telegram_request_failures_total",
+            "Telegram API requests that failed while handling an update",
+        )
+        .expect("valid metric");
+        let active_chats = IntGauge::new("bot_active_chats", "Chats with stored state").expect("valid metric");
+        let nova_circuit_state = IntGauge::new(
+            "nova_circuit_breaker_state",
+            "Nova gateway circuit breaker state (0 closed, 1 half-open, 2 open)",
+        )
+        .expect("valid metric");
+
+        registry.register(Box::new(commands.clone())).expect("unique metric");
+        registry.register(Box::new(callbacks.clone())).expect("unique metric");
+        registry.register(Box::new(rate_limited.clone())).expect("unique metric");
+        registry.register(Box::new(nova_request_duration.clone())).expect("unique metric");
+        registry.register(Box::new(nova_responses.clone())).expect("unique metric");
+        registry.register(Box::new(nova_rate_limit_retries.clone())).expect("unique metric");
+        registry.register(Box::new(telegram_failures.clone())).expect("unique metric");
+        registry.register(Box::new(active_chats.clone())).expect("unique metric");
+        registry.register(Box::new(nova_circuit_state.clone())).expect("unique metric");
+
+        Self {
+            registry,
+            commands,
+            callbacks,
+            rate_limited,
+            nova_request_duration,
+            nova_responses,
+            nova_rate_limit_retries,
+            telegram_failures,
+            active_chats,
+            nova_circuit_state,
+        }
+    }
+
+    /// Starts timing a Nova request; the latency is recorded when the
+    /// returned timer is dropped.
+    pub fn nova_timer(&self, operation: &str) -> prometheus::HistogramTimer {
+        self.nova_request_duration.with_label_values(&[operation]).start_timer()
+    }
+
+    pub fn record_nova_status(&self, operation: &str, status: Option<u16>) {
+        let status = status.map_or_else(|| "error".to_string(), |status| status.to_string());
+        self.nova_responses.with_label_values(&[operation, &status]).inc();
+    }
+
+    /// Renders all metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        let encoder = TextEncoder::new();
+        if encoder.encode(&self.registry.gather(), &mut buffer).is_err() {
+            return String::new();
+        }
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+}
@@ -0,0 +1,202 @@
+use std::{sync::Mutex, time::Instant};
+
+use crate::{config::dto::CircuitBreakerSettings, metrics::METRICS};
+
+/// Stops calling the gateway after repeated failures. Once
+/// `failure_threshold` consecutive calls fail the circuit opens and calls
+/// fail fast for `cooldown`. After that a single probe call is let through
+/// (half-open): its success closes the circuit, its failure opens it again.
+pub struct CircuitBreaker {
+    settings: CircuitBreakerSettings,
+    state: Mutex<BreakerState>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum BreakerState {
+    Closed { failures: u32 },
+    Open { until: Instant },
+    HalfOpen,
+}
+
+/// Permission to make one gateway call. Dropping it without reporting an
+/// outcome (e.g. when the call is cancelled) frees the probe slot.
+pub struct Permit<'a> {
+    breaker: &'a CircuitBreaker,
+    probe: bool,
+    settled: bool,
+}
+
+impl CircuitBreaker {
+    pub fn new(settings: CircuitBreakerSettings) -> Self {
+        Self {
+            settings,
+            state: Mutex::new(BreakerState::Closed { failures: 0 }),
+        }
+    }
+
+    /// Returns a permit if the call may proceed, or `None` to fail fast.
+    pub fn acquire(&self) -> Option<Permit<'_>> {
+        if self.settings.failure_threshold == 0 {
+            return Some(self.permit(false));
+        }
+
+        let mut state = self.lock();
+        match *state {
+            BreakerState::Closed { .. } => Some(self.permit(false)),
+            BreakerState::Open { until } if Instant::now() >= until => {
+                self.transition(&mut state, BreakerState::HalfOpen);
+                Some(self.permit(true))
+            }
+            BreakerState::Open { .. } | BreakerState::HalfOpen => None,
+        }
+    }
+
+    fn permit(&self, probe: bool) -> Permit<'_> {
+        Permit {
+            breaker: self,
+            probe,
+            settled: false,
+        }
+    }
+
+    fn record(&self, success: bool) {
+        if self.settings.failure_threshold == 0 {
+            return;
+        }
+
+        let mut state = self.lock();
+        let next = match (*state, success) {
+            (_, true) => BreakerState::Closed { failures: 0 },
+            (BreakerState::Closed { failures }, false) if failures + 1 < self.settings.failure_threshold => {
+                BreakerState::Closed { failures: failures + 1 }
+            }
+            (_, false) => BreakerState::Open {
+                until: Instant::now() + self.settings.cooldown,
+            },
+        };
+        self.transition(&mut state, next);
+    }
+
+    fn release_probe(&self) {
+        let mut state = self.lock();
+        if matches!(*state, BreakerState::HalfOpen) {
+            self.transition(&mut state, BreakerState::Open { until: Instant::now() });
+        }
+    }
+
+    fn transition(&self, state: &mut BreakerState, next: BreakerState) {
+        let gauge = match next {
+            BreakerState::Closed { .. } => 0,
+            BreakerState::HalfOpen => 1,
+            BreakerState::Open { .. } => 2,
+        };
+        if matches!((*state, next), (BreakerState::Closed { .. } | BreakerState::HalfOpen, BreakerState::Open { .. })) {
+            tracing::warn!(cooldown_secs = self.settings.cooldown.as_secs(), "nova circuit breaker opened");
+        }
+        METRICS.nova_circuit_state.set(gauge);
+        *state = next;
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BreakerState> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Permit<'_> {
+    pub fn succeed(mut self) {
+        self.settled = true;
+        self.breaker.record(true);
+    }
+
+    pub fn fail(mut self) {
+        self.settled = true;
+        self.breaker.record(false);
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        if self.probe && !self.settled {
+            self.breaker.release_probe();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn breaker(failure_threshold: u32, cooldown: Duration) -> CircuitBreaker {
+        CircuitBreaker::new(CircuitBreakerSettings {
+            failure_threshold,
+            cooldown,
+        })
+    }
+
+    fn state(breaker: &CircuitBreaker) -> BreakerState {
+        *breaker.lock()
+    }
+
+    #[test]
+    fn opens_after_consecutive_failures() {
+        let breaker = breaker(2, Duration::from_secs(60));
+        breaker.acquire().unwrap().fail();
+        assert!(matches!(state(&breaker), BreakerState::Closed { failures: 1 }));
+        breaker.acquire().unwrap().succeed();
+        assert!(matches!(state(&breaker), BreakerState::Closed { failures: 0 }));
+
+        breaker.acquire().unwrap().fail();
+        breaker.acquire().unwrap().fail();
+        assert!(matches!(state(&breaker), BreakerState::Open { .. }));
+        assert!(breaker.acquire().is_none());
+    }
+
+    #[test]
+    fn closes_after_a_successful_half_open_probe() {
+        let breaker = breaker(1, Duration::ZERO);
+        breaker.acquire().unwrap().fail();
+        assert!(matches!(state(&breaker), BreakerState::Open { .. }));
+
+        let probe = breaker.acquire().unwrap();
+        assert!(matches!(state(&breaker), BreakerState::HalfOpen));
+        assert!(breaker.acquire().is_none(), "only one probe at a time");
+
+        probe.succeed();
+        assert!(matches!(state(&breaker), BreakerState::Closed { failures: 0 }));
+        assert!(breaker.acquire().is_some());
+    }
+
+    #[test]
+    fn reopens_after_a_failed_half_open_probe() {
+        let breaker = breaker(3, Duration::ZERO);
+        for _ in 0..3 {
+            breaker.acquire().unwrap().fail();
+        }
+
+        let probe = breaker.acquire().unwrap();
+        assert!(matches!(state(&breaker), BreakerState::HalfOpen));
+        probe.fail();
+        assert!(matches!(state(&breaker), BreakerState::Open { .. }));
+    }
+
+    #[test]
+    fn frees_the_probe_slot_when_a_probe_is_dropped() {
+        let breaker = breaker(1, Duration::ZERO);
+        breaker.acquire().unwrap().fail();
+
+        drop(breaker.acquire().unwrap());
+        assert!(matches!(state(&breaker), BreakerState::Open { .. }));
+        assert!(breaker.acquire().is_some());
+    }
+
+    #[test]
+    fn never_opens_with_a_zero_threshold() {
+        let breaker = breaker(0, Duration::from_secs(60));
+        for _ in 0..10 {
+            breaker.acquire().unwrap().fail();
+        }
+        assert!(breaker.acquire().is_some());
+    }
+}
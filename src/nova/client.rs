@@ -1,135 +1,408 @@
-use std::time::Duration;
+use std::{
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use reqwest::{Client, StatusCode};
-use serde_json;
+use reqwest::{
+    header::{HeaderValue, ACCEPT, CONTENT_TYPE},
+    Client, RequestBuilder, Response, StatusCode,
+};
 use thiserror::Error;
-use tokio::time::sleep;
+use tokio::{
+    sync::mpsc,
+    time::{sleep, timeout},
+};
 
-use super::{dto::NovaRequest, dto::NovaResponse, dto::NovaErrorResponse, helpers};
+use crate::{
+    config::dto::{CircuitBreakerSettings, RetryPolicy},
+    metrics::METRICS,
+    telemetry,
+};
+
+use super::{breaker::CircuitBreaker, dto::NovaRequest, dto::NovaResponse, dto::NovaErrorResponse, helpers};
+
+const PROBE_TIMEOUT_SECS: u64 = 5;
+const CORRELATION_ID_HEADER: &str = "X-Correlation-ID";
 
 #[derive(Clone)]
 pub struct NovaClient {
     http_client: Client,
     base_url: String,
     api_key: String,
+    /// Limit for a whole request, or for the wait between two chunks of a
+    /// streamed answer.
+    timeout: Duration,
+    retry_policy: RetryPolicy,
+    breaker: Arc<CircuitBreaker>,
 }
 
 #[derive(Debug, Error)]
 pub enum NovaClientError {
     #[error("http error: {0}")]
-    Http(#[from] reqwest::Error),
+    Http(#[source] reqwest::Error),
     #[error("failed to build request headers: {0}")]
     Header(#[from] reqwest::header::InvalidHeaderValue),
-    #[error("nova gateway error ({status}): {message}")]
-    Gateway { status: u16, message: String },
+    #[error("nova gateway rejected the API key ({status}): {message}")]
+    Unauthorized { status: u16, message: String },
+    #[error("nova gateway quota exhausted ({status}): {message}")]
+    QuotaExhausted { status: u16, message: String },
+    #[error("nova gateway rate limit reached: {message}")]
+    RateLimited { retry_after: Option<Duration>, message: String },
+    #[error("nova gateway rejected the request ({status}): {message}")]
+    BadRequest { status: u16, message: String },
+    #[error("nova gateway upstream error ({status}): {message}")]
+    Upstream { status: u16, message: String },
+    #[error("nova gateway request timed out")]
+    Timeout,
+    #[error("nova gateway stream ended before the answer was complete")]
+    IncompleteStream,
+    #[error("nova gateway is unavailable (circuit breaker open)")]
+    Unavailable,
+}
+
+impl From<reqwest::Error> for NovaClientError {
+    fn from(error: reqwest::Error) -> Self {
+        if error.is_timeout() {
+            NovaClientError::Timeout
+        } else {
+            NovaClientError::Http(error)
+        }
+    }
+}
+
+impl NovaClientError {
+    /// HTTP status the gateway answered with, if the error came from a
+    /// gateway response.
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            NovaClientError::Unauthorized { status, .. }
+            | NovaClientError::QuotaExhausted { status, .. }
+            | NovaClientError::BadRequest { status, .. }
+            | NovaClientError::Upstream { status, .. } => Some(*status),
+            NovaClientError::RateLimited { .. } => Some(StatusCode::TOO_MANY_REQUESTS.as_u16()),
+            _ => None,
+        }
+    }
 }
 
 impl NovaClient {
-    pub fn new(api_key: String, base_url: String, timeout_secs: u64) -> Result<Self, NovaClientError> {
+    pub fn new(
+        api_key: String,
+        base_url: String,
+        timeout_secs: u64,
+        retry_policy: RetryPolicy,
+        breaker_settings: CircuitBreakerSettings,
+    ) -> Result<Self, NovaClientError> {
         let sanitized_base = base_url.trim_end_matches('/').to_string();
-        let http_client = Client::builder()
-            .timeout(Duration::from_secs(timeout_secs))
-            .build()?;
+        let timeout = Duration::from_secs(timeout_secs);
+        // No client-wide timeout: it would also cut off streamed answers
+        // that keep arriving. Each request sets its own instead.
+        let http_client = Client::builder().connect_timeout(timeout).build()?;
 
         Ok(Self {
             http_client,
             base_url: sanitized_base,
             api_key,
+            timeout,
+            retry_policy,
+            breaker: Arc::new(CircuitBreaker::new(breaker_settings)),
         })
     }
 
     pub async fn send_prompt(&self, request: NovaRequest) -> Result<NovaResponse, NovaClientError> {
+        timed("prompt", self.request_prompt(request)).await
+    }
+
+    async fn request_prompt(&self, request: NovaRequest) -> Result<NovaResponse, NovaClientError> {
         let url = format!("{}/ai", self.base_url);
         let headers = helpers::build_headers(&self.api_key)?;
-        let mut attempts = 0u8;
+        let response = self
+            .execute("prompt", || {
+                self.http_client.post(&url).headers(headers.clone()).json(&request).timeout(self.timeout)
+            })
+            .await?;
+
+        if response.status().is_success() {
+            return response.json::<NovaResponse>().await.map_err(NovaClientError::from);
+        }
+
+        Err(gateway_error(response).await)
+    }
+
+    /// Sends the prompt asking the gateway to stream its answer. Every text
+    /// delta is forwarded to `deltas` as it arrives and the assembled
+    /// response is returned once the stream ends. When the gateway answers
+    /// with a regular JSON body instead of an event stream, that body is
+    /// returned as-is and no deltas are sent. Instead of a limit for the
+    /// whole answer, the stream fails when no chunk arrives for the
+    /// configured timeout, or when it ends without its `[DONE]` event.
+    pub async fn stream_prompt(
+        &self,
+        request: NovaRequest,
+        deltas: mpsc::UnboundedSender<String>,
+    ) -> Result<NovaResponse, NovaClientError> {
+        timed("stream", self.request_stream(request, deltas)).await
+    }
+
+    async fn request_stream(
+        &self,
+        mut request: NovaRequest,
+        deltas: mpsc::UnboundedSender<String>,
+    ) -> Result<NovaResponse, NovaClientError> {
+        let url = format!("{}/ai", self.base_url);
+        let mut headers = helpers::build_headers(&self.api_key)?;
+        headers.insert(ACCEPT, HeaderValue::from_static("text/event-stream"));
+        request.stream = true;
+
+        let mut response = self
+            .execute("stream", || self.http_client.post(&url).headers(headers.clone()).json(&request))
+            .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(gateway_error(response).await);
+        }
+
+        let is_event_stream = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with("text/event-stream"));
+
+        if !is_event_stream {
+            return match timeout(self.timeout, response.json::<NovaResponse>()).await {
+                Ok(result) => result.map_err(NovaClientError::from),
+                Err(_) => Err(NovaClientError::Timeout),
+            };
+        }
+
+        let mut pending = Vec::new();
+        let mut buffer = String::new();
+        let mut text = String::new();
+        let mut usage = None;
 
         loop {
-            attempts = attempts.saturating_add(1);
-            let response = self
-                .http_client
-                .post(&url)
-                .headers(headers.clone())
-                .json(&request)
-                .send()
-                .await?;
-
-            let status = response.status();
-
-            if status == StatusCode::TOO_MANY_REQUESTS && attempts < 3 {
-                sleep(Duration::from_millis(500 * attempts as u64)).await;
-                continue;
+            let chunk = timeout(self.timeout, response.chunk())
+                .await
+                .map_err(|_| NovaClientError::Timeout)??;
+            let Some(chunk) = chunk else {
+                return Err(NovaClientError::IncompleteStream);
+            };
+            pending.extend_from_slice(&chunk);
+            // Only decode up to the last complete UTF-8 sequence; the rest
+            // waits for the next chunk.
+            let valid_up_to = match std::str::from_utf8(&pending) {
+                Ok(decoded) => decoded.len(),
+                Err(err) => err.valid_up_to(),
+            };
+            buffer.push_str(&String::from_utf8_lossy(&pending[..valid_up_to]));
+            pending.drain(..valid_up_to);
+
+            let events = helpers::drain_sse_events(&mut buffer);
+            usage = events.usage.or(usage);
+
+            for part in events.deltas {
+                text.push_str(&part);
+                let _ = deltas.send(part);
             }
 
-            if status.is_success() {
-                return response.json::<NovaResponse>().await.map_err(NovaClientError::from);
+            if events.done {
+                return Ok(NovaResponse { text: Some(text), usage });
             }
+        }
+    }
 
-            let response_text = response.text().await.unwrap_or_else(|_| String::new());
-            let message = match serde_json::from_str::<NovaErrorResponse>(&response_text) {
-                Ok(payload) => payload
-                    .message
-                    .unwrap_or_else(|| {
-                        if response_text.is_empty() {
-                            format!("request failed with status {}", status.as_u16())
-                        } else {
-                            format!("request failed with status {}: {}", status.as_u16(), response_text)
-                        }
-                    }),
-                Err(_) => {
-                    if response_text.is_empty() {
-                        format!("request failed with status {}", status.as_u16())
-                    } else {
-                        format!("request failed with status {}: {}", status.as_u16(), response_text)
-                    }
-                }
-            };
+    /// Lightweight check used by the readiness endpoint. The request carries
+    /// the API key, so a rejected key (401/403) or a wrong base URL (404)
+    /// counts as not ready, just like a 5xx answer.
+    pub async fn probe(&self) -> Result<(), NovaClientError> {
+        let url = format!("{}/health", self.base_url);
+        let headers = helpers::build_headers(&self.api_key)?;
+        let response = self
+            .http_client
+            .get(&url)
+            .headers(headers)
+            .timeout(Duration::from_secs(PROBE_TIMEOUT_SECS))
+            .send()
+            .await?;
 
-            return Err(NovaClientError::Gateway {
-                status: status.as_u16(),
-                message,
-            });
+        let status = response.status();
+        let unusable = matches!(status, StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN | StatusCode::NOT_FOUND);
+        if unusable || status.is_server_error() {
+            return Err(gateway_error(response).await);
         }
+        Ok(())
     }
 
     pub async fn clear_history(&self, ref_id: Option<String>) -> Result<(), NovaClientError> {
+        timed("clear_history", self.request_clear_history(ref_id)).await
+    }
+
+    async fn request_clear_history(&self, ref_id: Option<String>) -> Result<(), NovaClientError> {
         let url = format!("{}/ai", self.base_url);
         let headers = helpers::build_headers(&self.api_key)?;
-        let mut request = self.http_client.delete(&url).headers(headers);
+        let build = || {
+            let request = self.http_client.delete(&url).headers(headers.clone()).timeout(self.timeout);
+            match &ref_id {
+                Some(identifier) => request.query(&[("ref_id", identifier)]),
+                None => request,
+            }
+        };
+
+        let response = self.execute("clear_history", build).await?;
 
-        if let Some(identifier) = ref_id {
-            request = request.query(&[("ref_id", identifier)]);
+        if response.status().is_success() {
+            return Ok(());
         }
 
-        let response = request.send().await?;
-        let status = response.status();
+        Err(gateway_error(response).await)
+    }
 
-        if status.is_success() {
-            Ok(())
-        } else {
-            let response_text = response.text().await.unwrap_or_else(|_| String::new());
-            let message = match serde_json::from_str::<NovaErrorResponse>(&response_text) {
-                Ok(payload) => payload
-                    .message
-                    .unwrap_or_else(|| {
-                        if response_text.is_empty() {
-                            format!("failed to clear history: status {}", status.as_u16())
-                        } else {
-                            format!("failed to clear history: status {}: {}", status.as_u16(), response_text)
-                        }
-                    }),
-                Err(_) => {
-                    if response_text.is_empty() {
-                        format!("failed to clear history: status {}", status.as_u16())
-                    } else {
-                        format!("failed to clear history: status {}: {}", status.as_u16(), response_text)
-                    }
+    /// Sends the request through the circuit breaker. Transport errors and
+    /// 5xx answers that remain after retrying count as failures.
+    async fn execute(&self, operation: &str, build: impl Fn() -> RequestBuilder) -> Result<Response, NovaClientError> {
+        let permit = self.breaker.acquire().ok_or(NovaClientError::Unavailable)?;
+        let result = self.execute_with_retries(operation, build).await;
+
+        match &result {
+            Ok(response) if response.status().is_server_error() => permit.fail(),
+            Err(NovaClientError::Http(_) | NovaClientError::Timeout) => permit.fail(),
+            _ => permit.succeed(),
+        }
+        result
+    }
+
+    /// Sends the request produced by `build`, retrying timeouts, connection
+    /// failures and retryable statuses as the retry policy allows. A
+    /// `Retry-After` header replaces the computed backoff; when it asks for
+    /// more than the maximum delay the response is returned instead.
+    async fn execute_with_retries(
+        &self,
+        operation: &str,
+        build: impl Fn() -> RequestBuilder,
+    ) -> Result<Response, NovaClientError> {
+        let policy = &self.retry_policy;
+        let mut attempt = 0;
+
+        loop {
+            attempt += 1;
+            let result = send_recorded(operation, build(), self.timeout).await;
+            let retries_left = attempt < policy.max_attempts;
+
+            let (delay, status) = match &result {
+                Ok(response) if retries_left && policy.is_retryable_status(response.status().as_u16()) => {
+                    let delay = match helpers::retry_after(response.headers()) {
+                        Some(wait) if wait > policy.max_delay => None,
+                        Some(wait) => Some(wait),
+                        None => Some(policy.backoff(attempt)),
+                    };
+                    (delay, Some(response.status()))
                 }
+                Err(NovaClientError::Http(err)) if retries_left && is_transient(err) => {
+                    (Some(policy.backoff(attempt)), None)
+                }
+                Err(NovaClientError::Timeout) if retries_left => (Some(policy.backoff(attempt)), None),
+                _ => (None, None),
             };
 
-            Err(NovaClientError::Gateway {
-                status: status.as_u16(),
-                message,
-            })
+            let Some(delay) = delay else {
+                return result;
+            };
+
+            if status == Some(StatusCode::TOO_MANY_REQUESTS) {
+                METRICS.nova_rate_limit_retries.inc();
+            }
+            tracing::info!(
+                operation,
+                attempt,
+                status = status.map(|status| status.as_u16()),
+                delay_ms = delay.as_millis() as u64,
+                "retrying nova request"
+            );
+            sleep(delay).await;
         }
     }
 }
+
+/// Transport errors worth retrying: the request may not have reached the
+/// gateway. Timeouts are already mapped to [`NovaClientError::Timeout`].
+fn is_transient(error: &reqwest::Error) -> bool {
+    error.is_connect() || error.is_request()
+}
+
+/// Runs a whole gateway operation, from the first attempt to the last byte
+/// of the answer, recording its latency.
+async fn timed<T>(
+    operation: &str,
+    future: impl Future<Output = Result<T, NovaClientError>>,
+) -> Result<T, NovaClientError> {
+    let started = Instant::now();
+    let _timer = METRICS.nova_timer(operation);
+    let result = future.await;
+
+    let elapsed_ms = started.elapsed().as_millis() as u64;
+    match &result {
+        Ok(_) => tracing::info!(operation, elapsed_ms, "nova request finished"),
+        Err(err) => tracing::warn!(operation, elapsed_ms, status = err.status(), error = %err, "nova request failed"),
+    }
+    result
+}
+
+/// Sends the request tagged with the current correlation id, recording the
+/// status code it was answered with. Waiting for the response headers is
+/// limited to `limit` even for requests without a timeout of their own.
+async fn send_recorded(
+    operation: &str,
+    mut request: RequestBuilder,
+    limit: Duration,
+) -> Result<Response, NovaClientError> {
+    if let Some(correlation_id) = telemetry::correlation_id() {
+        request = request.header(CORRELATION_ID_HEADER, correlation_id);
+    }
+
+    let result = match timeout(limit, request.send()).await {
+        Ok(result) => result,
+        Err(_) => {
+            METRICS.record_nova_status(operation, None);
+            return Err(NovaClientError::Timeout);
+        }
+    };
+    let status = result.as_ref().ok().map(|response| response.status().as_u16());
+    METRICS.record_nova_status(operation, status);
+    tracing::debug!(operation, status, "nova response received");
+    Ok(result?)
+}
+
+/// Turns an unsuccessful gateway response into a typed error, using the
+/// status code and, where the status is ambiguous, the error body.
+async fn gateway_error(response: Response) -> NovaClientError {
+    let status = response.status();
+    let retry_after = helpers::retry_after(response.headers());
+    let response_text = response.text().await.unwrap_or_else(|_| String::new());
+    let payload = serde_json::from_str::<NovaErrorResponse>(&response_text).ok();
+    let code = payload
+        .as_ref()
+        .map(|payload| {
+            let error = payload.error.as_ref().map(|error| error.to_string()).unwrap_or_default();
+            format!("{} {error}", payload.code.as_deref().unwrap_or_default())
+        })
+        .unwrap_or_default();
+    let message = match payload.and_then(|payload| payload.message) {
+        Some(message) => message,
+        None if response_text.is_empty() => format!("request failed with status {}", status.as_u16()),
+        None => format!("request failed with status {}: {}", status.as_u16(), response_text),
+    };
+
+    let status = status.as_u16();
+    let quota = helpers::is_quota_error(&code) || helpers::is_quota_error(&message);
+    match status {
+        402 => NovaClientError::QuotaExhausted { status, message },
+        401 | 403 | 429 if quota => NovaClientError::QuotaExhausted { status, message },
+        401 | 403 => NovaClientError::Unauthorized { status, message },
+        429 => NovaClientError::RateLimited { retry_after, message },
+        408 | 504 => NovaClientError::Timeout,
+        500..=599 => NovaClientError::Upstream { status, message },
+        _ => NovaClientError::BadRequest { status, message },
+    }
+}
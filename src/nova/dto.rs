@@ -1,10 +1,12 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct NovaRequest {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub ref_id: Option<String>,
     pub input: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instructions: Option<String>,
     pub model: String,
     pub verbosity: String,
     pub max_tokens: u32,
@@ -12,6 +14,8 @@ pub struct NovaRequest {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub reasoning_params: Option<NovaReasoningParams>,
     pub image_urls: Vec<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub stream: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -24,9 +28,46 @@ pub struct NovaReasoningParams {
 pub struct NovaResponse {
     #[serde(default)]
     pub text: Option<String>,
+    #[serde(default)]
+    pub usage: Option<NovaUsage>,
+}
+
+/// Token usage reported by the gateway, in either its own or the OpenAI
+/// field names.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct NovaUsage {
+    #[serde(default, alias = "prompt_tokens")]
+    pub input_tokens: Option<u64>,
+    #[serde(default, alias = "completion_tokens")]
+    pub output_tokens: Option<u64>,
+    #[serde(default)]
+    pub total_tokens: Option<u64>,
+}
+
+impl NovaUsage {
+    pub fn total(&self) -> Option<u64> {
+        match (self.total_tokens, self.input_tokens, self.output_tokens) {
+            (Some(total), _, _) => Some(total),
+            (None, None, None) => None,
+            (None, input, output) => Some(input.unwrap_or_default() + output.unwrap_or_default()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NovaStreamChunk {
+    #[serde(default, alias = "text")]
+    pub delta: Option<String>,
+    /// Usually only present on the last chunk.
+    #[serde(default)]
+    pub usage: Option<NovaUsage>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct NovaErrorResponse {
     pub message: Option<String>,
+    #[serde(default)]
+    pub code: Option<String>,
+    #[serde(default)]
+    pub error: Option<serde_json::Value>,
 }
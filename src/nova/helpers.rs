@@ -1,8 +1,19 @@
-use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE};
+use std::time::Duration;
 
-use crate::config::dto::ReasoningSettings;
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE, RETRY_AFTER};
 
-use super::dto::{NovaReasoningParams, NovaRequest};
+use crate::config::dto::{InstructionMode, ReasoningSettings};
+
+use super::dto::{NovaReasoningParams, NovaRequest, NovaStreamChunk, NovaUsage};
+
+/// What [`drain_sse_events`] found in the buffered stream.
+#[derive(Debug, Default)]
+pub struct SseEvents {
+    pub deltas: Vec<String>,
+    pub usage: Option<NovaUsage>,
+    /// `true` once the `[DONE]` sentinel has been seen.
+    pub done: bool,
+}
 
 pub fn build_headers(api_key: &str) -> Result<HeaderMap, reqwest::header::InvalidHeaderValue> {
     let mut headers = HeaderMap::new();
@@ -12,9 +23,40 @@ pub fn build_headers(api_key: &str) -> Result<HeaderMap, reqwest::header::Invali
     Ok(headers)
 }
 
+/// Parses a `Retry-After` header given in seconds. The HTTP-date form is
+/// not supported and yields `None`.
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    headers
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+/// Status codes a gateway without streaming support answers a streaming
+/// request with; the caller should retry without streaming. A 400 is left
+/// out because it usually means the request itself is wrong and would fail
+/// again.
+pub fn is_streaming_unsupported(status: u16) -> bool {
+    matches!(status, 404 | 405 | 406 | 415 | 501)
+}
+
+/// Whether an error code or message reports an exhausted balance or quota,
+/// which some gateways send with a 401, 403 or 429 status.
+pub fn is_quota_error(text: &str) -> bool {
+    let text = text.to_lowercase();
+    ["insufficient", "balance", "quota", "credit"]
+        .iter()
+        .any(|keyword| text.contains(keyword))
+}
+
 pub fn create_request(
     ref_id: Option<String>,
     input: String,
+    image_urls: Vec<String>,
     model: &str,
     verbosity: &str,
     max_tokens: u32,
@@ -31,11 +73,144 @@ pub fn create_request(
     NovaRequest {
         ref_id,
         input,
+        instructions: None,
         model: model.to_string(),
         verbosity: verbosity.to_lowercase(),
         max_tokens,
         reasoning: reasoning.enabled,
         reasoning_params,
-        image_urls: Vec::new(),
+        image_urls,
+        stream: false,
+    }
+}
+
+/// Adds a system prompt to the request, either in the `instructions` field
+/// or as a block in front of the user's message.
+pub fn apply_instructions(request: &mut NovaRequest, instructions: &str, mode: InstructionMode) {
+    match mode {
+        InstructionMode::Field => request.instructions = Some(instructions.to_string()),
+        InstructionMode::Prepend => {
+            request.input = format!("Instructions:\n{instructions}\n\nUser message:\n{}", request.input);
+        }
+    }
+}
+
+/// Rough token count for text the gateway reported no usage for, at about
+/// four characters per token.
+pub fn estimate_tokens(text: &str) -> u64 {
+    (text.chars().count() as u64).div_ceil(4)
+}
+
+/// Pops every complete server-sent event from `buffer` and returns the
+/// text deltas and usage it carried. The `data:` lines of one event are
+/// joined with newlines, as the SSE format specifies. Incomplete trailing
+/// data stays in the buffer.
+pub fn drain_sse_events(buffer: &mut String) -> SseEvents {
+    let mut events = SseEvents::default();
+
+    if buffer.contains('\r') {
+        *buffer = buffer.replace("\r\n", "\n");
+    }
+
+    while let Some(end) = buffer.find("\n\n") {
+        let event: String = buffer.drain(..end + 2).collect();
+        let data: Vec<&str> = event
+            .lines()
+            .filter_map(|line| line.strip_prefix("data:"))
+            .map(|data| data.strip_prefix(' ').unwrap_or(data))
+            .collect();
+        if data.is_empty() {
+            continue;
+        }
+        let data = data.join("\n");
+        let data = data.trim();
+
+        if data == "[DONE]" {
+            events.done = true;
+            continue;
+        }
+
+        let Ok(chunk) = serde_json::from_str::<NovaStreamChunk>(data) else {
+            continue;
+        };
+        if let Some(usage) = chunk.usage {
+            events.usage = Some(usage);
+        }
+        if let Some(delta) = chunk.delta.filter(|delta| !delta.is_empty()) {
+            events.deltas.push(delta);
+        }
+    }
+
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(retry_after: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_str(retry_after).unwrap());
+        headers
+    }
+
+    #[test]
+    fn parses_retry_after_seconds() {
+        assert_eq!(retry_after(&headers("30")), Some(Duration::from_secs(30)));
+        assert_eq!(retry_after(&headers(" 0 ")), Some(Duration::ZERO));
+        assert_eq!(retry_after(&headers("Wed, 21 Oct 2015 07:28:00 GMT")), None);
+        assert_eq!(retry_after(&headers("-1")), None);
+        assert_eq!(retry_after(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn keeps_events_split_across_chunks_until_complete() {
+        let mut buffer = "data: {\"delta\":\"Hel".to_string();
+        let events = drain_sse_events(&mut buffer);
+        assert!(events.deltas.is_empty());
+        assert_eq!(buffer, "data: {\"delta\":\"Hel");
+
+        buffer.push_str("lo\"}\n");
+        assert!(drain_sse_events(&mut buffer).deltas.is_empty());
+
+        buffer.push_str("\ndata: {\"delta\":\" world\"}\n\ndata: {\"del");
+        let events = drain_sse_events(&mut buffer);
+        assert_eq!(events.deltas, ["Hello", " world"]);
+        assert_eq!(buffer, "data: {\"del");
+    }
+
+    #[test]
+    fn accepts_crlf_line_endings() {
+        let mut buffer = "data: {\"delta\":\"a\"}\r\n\r\ndata: {\"delta\":\"b\"}\r".to_string();
+        assert_eq!(drain_sse_events(&mut buffer).deltas, ["a"]);
+
+        buffer.push_str("\n\r\n");
+        assert_eq!(drain_sse_events(&mut buffer).deltas, ["b"]);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn joins_multi_line_data() {
+        let mut buffer = "event: message\ndata: {\"delta\":\ndata: \"joined\"}\n\n".to_string();
+        assert_eq!(drain_sse_events(&mut buffer).deltas, ["joined"]);
+    }
+
+    #[test]
+    fn reads_usage_only_events() {
+        let mut buffer = "data: {\"usage\":{\"prompt_tokens\":3,\"completion_tokens\":4}}\n\n".to_string();
+        let events = drain_sse_events(&mut buffer);
+        assert!(events.deltas.is_empty());
+        let usage = events.usage.unwrap();
+        assert_eq!((usage.input_tokens, usage.output_tokens), (Some(3), Some(4)));
+        assert_eq!(usage.total(), Some(7));
+        assert!(!events.done);
+    }
+
+    #[test]
+    fn stops_at_done() {
+        let mut buffer = "data: {\"delta\":\"last\"}\n\n: keep-alive\n\ndata: [DONE]\n\n".to_string();
+        let events = drain_sse_events(&mut buffer);
+        assert_eq!(events.deltas, ["last"]);
+        assert!(events.done);
     }
 }
@@ -1,3 +1,4 @@
+mod breaker;
 mod client;
 pub mod dto;
 pub mod helpers;
@@ -0,0 +1,398 @@
+use std::{
+    convert::Infallible,
+    future::Future,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    extract::State,
+    http::{header::CONTENT_TYPE, Request, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use futures::{stream, Stream};
+use serde::Serialize;
+use teloxide::{
+    payloads::GetUpdatesSetters,
+    prelude::Requester,
+    stop::{mk_stop_token, StopFlag, StopToken},
+    types::{AllowedUpdate, Update},
+    update_listeners::{webhooks, StatefulListener, UpdateListener},
+    Bot, RequestError,
+};
+use tokio::sync::Mutex;
+
+use crate::{
+    config::Config,
+    metrics::METRICS,
+    nova::{NovaClient, NovaClientError},
+    store::ChatStateStore,
+    DynError,
+};
+
+/// Liveness information shared between the dispatcher and the health
+/// endpoints.
+pub struct Health {
+    started_at: Instant,
+    dispatcher_running: AtomicBool,
+    /// Unix time in seconds of the last successful poll or webhook request;
+    /// `0` means none yet.
+    last_update_at: AtomicU64,
+}
+
+impl Health {
+    pub fn new() -> Self {
+        Self {
+            started_at: Instant::now(),
+            dispatcher_running: AtomicBool::new(false),
+            last_update_at: AtomicU64::new(0),
+        }
+    }
+
+    pub fn set_dispatcher_running(&self, running: bool) {
+        self.dispatcher_running.store(running, Ordering::Relaxed);
+    }
+
+    /// Called after every successful `getUpdates` call or webhook request,
+    /// whether or not it carried any updates.
+    fn record_update(&self) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default();
+        self.last_update_at.store(now, Ordering::Relaxed);
+    }
+
+    fn dispatcher_running(&self) -> bool {
+        self.dispatcher_running.load(Ordering::Relaxed)
+    }
+
+    fn last_update_at(&self) -> Option<u64> {
+        match self.last_update_at.load(Ordering::Relaxed) {
+            0 => None,
+            timestamp => Some(timestamp),
+        }
+    }
+}
+
+/// How long a `/readyz` probe result is reused before Telegram and the Nova
+/// gateway are asked again.
+const PROBE_CACHE_TTL: Duration = Duration::from_secs(5);
+
+#[derive(Clone)]
+struct AppState {
+    health: Arc<Health>,
+    bot: Bot,
+    nova_client: NovaClient,
+    probes: Arc<Mutex<Option<ProbeResults>>>,
+}
+
+#[derive(Clone)]
+struct ProbeResults {
+    checked_at: Instant,
+    telegram: CheckResult,
+    nova: CheckResult,
+}
+
+#[derive(Serialize)]
+struct LivenessReport {
+    status: &'static str,
+    dispatcher_running: bool,
+    uptime_seconds: u64,
+    last_update_at: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct ReadinessReport {
+    status: &'static str,
+    dispatcher_running: bool,
+    last_update_at: Option<u64>,
+    telegram: CheckResult,
+    nova: CheckResult,
+}
+
+#[derive(Clone, Serialize)]
+struct CheckResult {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl CheckResult {
+    /// Logs the details of a failed check and reports only `reason`, so
+    /// URLs and response bodies never reach the unauthenticated endpoint.
+    fn new<E: std::fmt::Display>(
+        check: &'static str,
+        result: Result<(), E>,
+        reason: impl FnOnce(&E) -> &'static str,
+    ) -> Self {
+        match result {
+            Ok(()) => Self { ok: true, error: None },
+            Err(err) => {
+                tracing::warn!(check, error = %err, "readiness check failed");
+                Self {
+                    ok: false,
+                    error: Some(reason(&err).to_string()),
+                }
+            }
+        }
+    }
+}
+
+/// `/healthz` and `/readyz` routes.
+pub fn health_router(health: Arc<Health>, bot: Bot, nova_client: NovaClient) -> Router {
+    Router::new()
+        .route("/healthz", get(liveness))
+        .route("/readyz", get(readiness))
+        .with_state(AppState {
+            health,
+            bot,
+            nova_client,
+            probes: Arc::default(),
+        })
+}
+
+/// Alive as long as the dispatcher is running.
+async fn liveness(State(state): State<AppState>) -> (StatusCode, Json<LivenessReport>) {
+    let running = state.health.dispatcher_running();
+    let report = LivenessReport {
+        status: if running { "ok" } else { "unavailable" },
+        dispatcher_running: running,
+        uptime_seconds: state.health.started_at.elapsed().as_secs(),
+        last_update_at: state.health.last_update_at(),
+    };
+    (status_code(running), Json(report))
+}
+
+/// Ready when the dispatcher is running and both Telegram and the Nova
+/// gateway answer.
+async fn readiness(State(state): State<AppState>) -> (StatusCode, Json<ReadinessReport>) {
+    let ProbeResults { telegram, nova, .. } = probe(&state).await;
+
+    let running = state.health.dispatcher_running();
+    let ready = running && telegram.ok && nova.ok;
+    let report = ReadinessReport {
+        status: if ready { "ok" } else { "unavailable" },
+        dispatcher_running: running,
+        last_update_at: state.health.last_update_at(),
+        telegram,
+        nova,
+    };
+    (status_code(ready), Json(report))
+}
+
+/// Asks Telegram and the Nova gateway, reusing the previous answers for
+/// [`PROBE_CACHE_TTL`]. Concurrent requests wait for the probe in flight
+/// instead of starting their own.
+async fn probe(state: &AppState) -> ProbeResults {
+    let mut cached = state.probes.lock().await;
+    if let Some(results) = cached.as_ref()
+        && results.checked_at.elapsed() < PROBE_CACHE_TTL
+    {
+        return results.clone();
+    }
+
+    let (telegram, nova) = tokio::join!(
+        async { state.bot.get_me().await.map(|_| ()) },
+        state.nova_client.probe(),
+    );
+    let results = ProbeResults {
+        checked_at: Instant::now(),
+        telegram: CheckResult::new("telegram", telegram, |_| "Telegram Bot API unreachable"),
+        nova: CheckResult::new("nova", nova, |err| match err {
+            NovaClientError::Unauthorized { .. } => "API key rejected",
+            NovaClientError::BadRequest { status: 404, .. } => "health route not found",
+            NovaClientError::Timeout => "timed out",
+            _ => "gateway unreachable",
+        }),
+    };
+    *cached = Some(results.clone());
+    results
+}
+
+fn status_code(ok: bool) -> StatusCode {
+    if ok {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    }
+}
+
+/// `/metrics` route in the Prometheus text format.
+pub fn metrics_router(store: Arc<dyn ChatStateStore>) -> Router {
+    Router::new().route("/metrics", get(metrics)).with_state(store)
+}
+
+async fn metrics(State(store): State<Arc<dyn ChatStateStore>>) -> impl IntoResponse {
+    if let Ok(count) = store.chat_count().await {
+        METRICS.active_chats.set(i64::try_from(count).unwrap_or(i64::MAX));
+    }
+    ([(CONTENT_TYPE, "text/plain; version=0.0.4")], METRICS.render())
+}
+
+/// Registers the webhook with Telegram and starts an HTTP server on `PORT`
+/// that feeds incoming updates into the returned listener, next to the
+/// `routes` passed in. Requests without the configured secret token header
+/// are rejected; accepted ones are recorded in `health`.
+pub async fn webhook_listener(
+    bot: Bot,
+    config: &Config,
+    health: Arc<Health>,
+    routes: Router,
+) -> Result<impl UpdateListener<Err = Infallible> + use<>, DynError> {
+    let url = config
+        .webhook_url()
+        .cloned()
+        .ok_or("TELEGRAM_WEBHOOK_URL is required in webhook mode")?;
+    let address = listen_address(config);
+
+    let mut options = webhooks::Options::new(address, url);
+    if let Some(secret) = config.webhook_secret() {
+        options = options.secret_token(secret.to_string());
+    }
+
+    let (listener, stop_flag, router) = webhooks::axum_to_router(bot, options).await?;
+    let router = router.layer(middleware::from_fn_with_state(health, record_webhook_request));
+    spawn_server(address, router.merge(routes), stop_flag)?;
+    Ok(listener)
+}
+
+async fn record_webhook_request<B>(
+    State(health): State<Arc<Health>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let response = next.run(request).await;
+    if response.status().is_success() {
+        health.record_update();
+    }
+    response
+}
+
+/// Long-poll timeout passed to `getUpdates`, matching teloxide's default
+/// polling listener.
+const POLL_TIMEOUT_SECONDS: u32 = 10;
+
+/// Deletes any registered webhook and returns a long-polling listener that
+/// records every successful `getUpdates` call in `health`, including the
+/// ones that time out without updates.
+pub async fn polling_listener(
+    bot: Bot,
+    health: Arc<Health>,
+) -> Result<impl UpdateListener<Err = RequestError>, DynError> {
+    bot.delete_webhook().await?;
+
+    let (stop_token, stop_flag) = mk_stop_token();
+    let state = Polling {
+        bot,
+        health,
+        offset: 0,
+        allowed_updates: None,
+        stop_token,
+        stop_flag,
+    };
+    Ok(StatefulListener::new_with_hints(
+        state,
+        poll_updates,
+        |state: &mut Polling| state.stop_token.clone(),
+        Some(|state: &mut Polling, hint: &mut dyn Iterator<Item = AllowedUpdate>| {
+            state.allowed_updates = Some(hint.collect());
+        }),
+        Some(|_: &Polling| Some(Duration::from_secs(POLL_TIMEOUT_SECONDS.into()))),
+    ))
+}
+
+struct Polling {
+    bot: Bot,
+    health: Arc<Health>,
+    /// Identifier of the next update to ask for.
+    offset: i32,
+    /// Sent with the next `getUpdates` call only, like teloxide does.
+    allowed_updates: Option<Vec<AllowedUpdate>>,
+    stop_token: StopToken,
+    stop_flag: StopFlag,
+}
+
+/// Yields updates from repeated `getUpdates` calls. Once the listener is
+/// stopped, one last call with a zero timeout confirms the offset to
+/// Telegram so that handled updates are not delivered again.
+fn poll_updates(polling: &mut Polling) -> impl Stream<Item = Result<Update, RequestError>> + Send + '_ {
+    stream::unfold(
+        (polling, Vec::new().into_iter(), false),
+        |(polling, mut buffer, mut finished)| async move {
+            loop {
+                if let Some(update) = buffer.next() {
+                    return Some((Ok(update), (polling, buffer, finished)));
+                }
+                if finished {
+                    return None;
+                }
+
+                let stopping = polling.stop_flag.is_stopped();
+                let mut request = polling.bot.get_updates().offset(polling.offset);
+                request = if stopping {
+                    request.timeout(0).limit(1)
+                } else {
+                    request.timeout(POLL_TIMEOUT_SECONDS)
+                };
+                if let Some(allowed_updates) = polling.allowed_updates.take() {
+                    request = request.allowed_updates(allowed_updates);
+                }
+
+                match request.await {
+                    Ok(updates) => {
+                        polling.health.record_update();
+                        if stopping {
+                            return None;
+                        }
+                        if let Some(last) = updates.last() {
+                            polling.offset = last.id + 1;
+                        }
+                        buffer = updates.into_iter();
+                    }
+                    Err(err) => {
+                        finished = stopping;
+                        return Some((Err(err), (polling, buffer, finished)));
+                    }
+                }
+            }
+        },
+    )
+}
+
+/// Serves `routes` on `PORT` for the lifetime of the process. Used in
+/// polling mode, where there is no webhook server to attach them to.
+pub fn serve(config: &Config, routes: Router) -> Result<(), DynError> {
+    spawn_server(listen_address(config), routes, std::future::pending())
+}
+
+fn listen_address(config: &Config) -> SocketAddr {
+    SocketAddr::from(([0, 0, 0, 0], config.port()))
+}
+
+/// Binds `address` right away so a port conflict fails startup, then serves
+/// `router` in the background until `shutdown` resolves.
+fn spawn_server(
+    address: SocketAddr,
+    router: Router,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> Result<(), DynError> {
+    let server = axum::Server::try_bind(&address)?
+        .serve(router.into_make_service())
+        .with_graceful_shutdown(shutdown);
+    tracing::info!(%address, "HTTP server listening");
+
+    tokio::spawn(async move {
+        if let Err(err) = server.await {
+            tracing::error!(error = %err, "HTTP server error");
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,138 @@
+use std::{
+    collections::HashMap,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::{fs, io::AsyncWriteExt, sync::Mutex};
+
+use crate::bot::dto::{AccessList, ChatState, UserState};
+
+use super::{AccessUpdate, ChatStateStore, StateUpdate, StoreError, UserUpdate};
+
+/// Keeps chat state in memory and writes all of it to a JSON file after
+/// every change. Writes go to a temporary file that is flushed to disk and
+/// then renamed over the previous one, so a crash never leaves a
+/// half-written file behind.
+pub struct JsonFileStore {
+    path: PathBuf,
+    contents: Mutex<Contents>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Contents {
+    chats: HashMap<i64, ChatState>,
+    users: HashMap<i64, UserState>,
+    access: AccessList,
+}
+
+impl JsonFileStore {
+    pub async fn open(path: impl Into<PathBuf>) -> Result<Self, StoreError> {
+        let path = path.into();
+        let contents = match fs::read(&path).await {
+            Ok(contents) => serde_json::from_slice(&contents)?,
+            Err(err) if err.kind() == ErrorKind::NotFound => Contents::default(),
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(Self {
+            path,
+            contents: Mutex::new(contents),
+        })
+    }
+
+    async fn persist(&self, contents: &Contents) -> Result<(), StoreError> {
+        let contents = serde_json::to_vec(contents)?;
+        let temp_path = temp_path(&self.path);
+        let mut file = fs::File::create(&temp_path).await?;
+        file.write_all(&contents).await?;
+        file.sync_all().await?;
+        drop(file);
+
+        fs::rename(&temp_path, &self.path).await?;
+        // The rename itself is only durable once the directory is synced
+        let directory = match self.path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        fs::File::open(directory).await?.sync_all().await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ChatStateStore for JsonFileStore {
+    async fn load(&self, chat_id: i64) -> Result<ChatState, StoreError> {
+        let contents = self.contents.lock().await;
+        Ok(contents.chats.get(&chat_id).cloned().unwrap_or_default())
+    }
+
+    async fn chat_count(&self) -> Result<usize, StoreError> {
+        Ok(self.contents.lock().await.chats.len())
+    }
+
+    async fn update(&self, chat_id: i64, apply: StateUpdate<'_>) -> Result<ChatState, StoreError> {
+        let mut contents = self.contents.lock().await;
+        let state = contents.chats.entry(chat_id).or_default();
+        let previous = state.clone();
+        apply(state);
+        let updated = state.clone();
+
+        if updated != previous
+            && let Err(err) = self.persist(&contents).await
+        {
+            contents.chats.insert(chat_id, previous);
+            return Err(err);
+        }
+        Ok(updated)
+    }
+
+    async fn load_user(&self, user_id: i64) -> Result<UserState, StoreError> {
+        let contents = self.contents.lock().await;
+        Ok(contents.users.get(&user_id).cloned().unwrap_or_default())
+    }
+
+    async fn update_user(&self, user_id: i64, apply: UserUpdate<'_>) -> Result<UserState, StoreError> {
+        let mut contents = self.contents.lock().await;
+        let user = contents.users.entry(user_id).or_default();
+        let previous = user.clone();
+        apply(user);
+        let updated = user.clone();
+
+        if updated != previous
+            && let Err(err) = self.persist(&contents).await
+        {
+            contents.users.insert(user_id, previous);
+            return Err(err);
+        }
+        Ok(updated)
+    }
+
+    async fn load_access(&self) -> Result<AccessList, StoreError> {
+        Ok(self.contents.lock().await.access.clone())
+    }
+
+    async fn update_access(&self, apply: AccessUpdate<'_>) -> Result<AccessList, StoreError> {
+        let mut contents = self.contents.lock().await;
+        let previous = contents.access.clone();
+        apply(&mut contents.access);
+        let updated = contents.access.clone();
+
+        if updated != previous
+            && let Err(err) = self.persist(&contents).await
+        {
+            contents.access = previous;
+            return Err(err);
+        }
+        Ok(updated)
+    }
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".tmp");
+    path.with_file_name(file_name)
+}
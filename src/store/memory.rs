@@ -0,0 +1,57 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use tokio::sync::Mutex;
+
+use crate::bot::dto::{AccessList, ChatState, UserState};
+
+use super::{AccessUpdate, ChatStateStore, StateUpdate, StoreError, UserUpdate};
+
+/// Keeps chat state in memory only; everything is lost on restart.
+#[derive(Default)]
+pub struct MemoryStore {
+    states: Mutex<HashMap<i64, ChatState>>,
+    users: Mutex<HashMap<i64, UserState>>,
+    access: Mutex<AccessList>,
+}
+
+#[async_trait]
+impl ChatStateStore for MemoryStore {
+    async fn load(&self, chat_id: i64) -> Result<ChatState, StoreError> {
+        let states = self.states.lock().await;
+        Ok(states.get(&chat_id).cloned().unwrap_or_default())
+    }
+
+    async fn chat_count(&self) -> Result<usize, StoreError> {
+        Ok(self.states.lock().await.len())
+    }
+
+    async fn update(&self, chat_id: i64, apply: StateUpdate<'_>) -> Result<ChatState, StoreError> {
+        let mut states = self.states.lock().await;
+        let state = states.entry(chat_id).or_default();
+        apply(state);
+        Ok(state.clone())
+    }
+
+    async fn load_user(&self, user_id: i64) -> Result<UserState, StoreError> {
+        let users = self.users.lock().await;
+        Ok(users.get(&user_id).cloned().unwrap_or_default())
+    }
+
+    async fn update_user(&self, user_id: i64, apply: UserUpdate<'_>) -> Result<UserState, StoreError> {
+        let mut users = self.users.lock().await;
+        let user = users.entry(user_id).or_default();
+        apply(user);
+        Ok(user.clone())
+    }
+
+    async fn load_access(&self) -> Result<AccessList, StoreError> {
+        Ok(self.access.lock().await.clone())
+    }
+
+    async fn update_access(&self, apply: AccessUpdate<'_>) -> Result<AccessList, StoreError> {
+        let mut access = self.access.lock().await;
+        apply(&mut access);
+        Ok(access.clone())
+    }
+}
@@ -0,0 +1,67 @@
+mod json_file;
+mod memory;
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use thiserror::Error;
+
+use crate::{
+    bot::dto::{AccessList, ChatState, UserState},
+    config::{dto::StateStoreKind, Config},
+};
+
+pub use json_file::JsonFileStore;
+pub use memory::MemoryStore;
+
+/// Change applied to a chat's state by [`ChatStateStore::update`].
+pub type StateUpdate<'a> = Box<dyn FnOnce(&mut ChatState) + Send + 'a>;
+
+/// Change applied to a user's state by [`ChatStateStore::update_user`].
+pub type UserUpdate<'a> = Box<dyn FnOnce(&mut UserState) + Send + 'a>;
+
+/// Change applied to the access list by [`ChatStateStore::update_access`].
+pub type AccessUpdate<'a> = Box<dyn FnOnce(&mut AccessList) + Send + 'a>;
+
+/// Storage for everything the bot remembers per chat and per user.
+#[async_trait]
+pub trait ChatStateStore: Send + Sync {
+    /// Returns the stored state, or the default state for unknown chats.
+    async fn load(&self, chat_id: i64) -> Result<ChatState, StoreError>;
+
+    /// Number of chats with stored state.
+    async fn chat_count(&self) -> Result<usize, StoreError>;
+
+    /// Applies `apply` to the chat's state atomically, persists the result
+    /// and returns it.
+    async fn update(&self, chat_id: i64, apply: StateUpdate<'_>) -> Result<ChatState, StoreError>;
+
+    /// Returns the stored state, or the default state for unknown users.
+    async fn load_user(&self, user_id: i64) -> Result<UserState, StoreError>;
+
+    /// Applies `apply` to the user's state atomically, persists the result
+    /// and returns it.
+    async fn update_user(&self, user_id: i64, apply: UserUpdate<'_>) -> Result<UserState, StoreError>;
+
+    /// Returns the users and chats granted or revoked access at runtime.
+    async fn load_access(&self) -> Result<AccessList, StoreError>;
+
+    /// Applies `apply` to the access list atomically, persists the result
+    /// and returns it.
+    async fn update_access(&self, apply: AccessUpdate<'_>) -> Result<AccessList, StoreError>;
+}
+
+#[derive(Debug, Error)]
+pub enum StoreError {
+    #[error("state file i/o error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("state file serialization error: {0}")]
+    Serialization(#[from] serde_json::Error),
+}
+
+pub async fn open(config: &Config) -> Result<Arc<dyn ChatStateStore>, StoreError> {
+    match config.state_store() {
+        StateStoreKind::Memory => Ok(Arc::new(MemoryStore::default())),
+        StateStoreKind::JsonFile => Ok(Arc::new(JsonFileStore::open(config.state_path()).await?)),
+    }
+}
@@ -0,0 +1,38 @@
+use std::future::Future;
+
+use tracing_subscriber::EnvFilter;
+use uuid::Uuid;
+
+use crate::config::{dto::LogFormat, Config};
+
+tokio::task_local! {
+    static CORRELATION_ID: String;
+}
+
+/// Installs the global `tracing` subscriber. `RUST_LOG` takes precedence
+/// over `LOG_LEVEL` when both are set.
+pub fn init(config: &Config) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(config.log_level()));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+
+    match config.log_format() {
+        LogFormat::Json => builder.json().flatten_event(true).with_current_span(true).init(),
+        LogFormat::Pretty => builder.init(),
+    }
+}
+
+pub fn new_correlation_id() -> String {
+    Uuid::new_v4().to_string()
+}
+
+/// Runs `future` with `id` as the correlation id seen by
+/// [`correlation_id`], so calls made while handling an update can be tied
+/// back to it.
+pub async fn with_correlation_id<F: Future>(id: String, future: F) -> F::Output {
+    CORRELATION_ID.scope(id, future).await
+}
+
+/// Correlation id of the update currently being handled, if any.
+pub fn correlation_id() -> Option<String> {
+    CORRELATION_ID.try_with(Clone::clone).ok()
+}
@@ -1,22 +1,530 @@
 use std::time::Duration;
 
+use base64::{engine::general_purpose::STANDARD, Engine};
 use teloxide::{
-    payloads::SendMessageSetters,
+    net::Download,
+    payloads::{
+        AnswerCallbackQuerySetters, EditMessageTextSetters, SendChatActionSetters,
+        SendMessageSetters,
+    },
     prelude::Requester,
-    types::{ChatAction, ChatId},
-    Bot, RequestError,
+    types::{CallbackQuery, ChatAction, ChatId, File, InlineKeyboardMarkup, Message, MessageId, MessageKind, ParseMode},
+    ApiError, Bot, DownloadError, RequestError,
 };
 use tokio::sync::oneshot;
 
-pub async fn send_text(bot: &Bot, chat_id: ChatId, text: impl Into<String>) -> Result<(), RequestError> {
-    bot.send_message(chat_id, text.into())
-        .disable_web_page_preview(true)
+use crate::markdown;
+
+/// Telegram rejects messages longer than this many UTF-16 code units.
+pub const TELEGRAM_MESSAGE_LIMIT: usize = 4096;
+
+/// Where a message is sent: a chat and, in forum supergroups, the topic
+/// within it. Messages sent without a topic land in the "General" topic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Destination {
+    pub chat_id: ChatId,
+    pub thread_id: Option<i32>,
+}
+
+impl Destination {
+    /// The chat and forum topic `message` was posted in. Outside forums
+    /// Telegram also sets a thread id on replies, which is ignored here.
+    pub fn of(message: &Message) -> Self {
+        let in_topic = matches!(&message.kind, MessageKind::Common(common) if common.is_topic_message);
+        Self {
+            chat_id: message.chat.id,
+            thread_id: message.thread_id.filter(|_| in_topic),
+        }
+    }
+}
+
+impl From<ChatId> for Destination {
+    fn from(chat_id: ChatId) -> Self {
+        Self { chat_id, thread_id: None }
+    }
+}
+
+fn new_message(bot: &Bot, to: Destination, text: String) -> <Bot as Requester>::SendMessage {
+    let request = bot.send_message(to.chat_id, text).disable_web_page_preview(true);
+    match to.thread_id {
+        Some(thread_id) => request.message_thread_id(thread_id),
+        None => request,
+    }
+}
+
+pub async fn send_reply(
+    bot: &Bot,
+    to: Destination,
+    text: impl Into<String>,
+    reply_to: MessageId,
+) -> Result<Message, RequestError> {
+    new_reply(bot, to, text.into(), reply_to, None).await
+}
+
+fn new_reply(
+    bot: &Bot,
+    to: Destination,
+    text: String,
+    reply_to: MessageId,
+    keyboard: Option<InlineKeyboardMarkup>,
+) -> <Bot as Requester>::SendMessage {
+    let request = new_message(bot, to, text)
+        .reply_to_message_id(reply_to)
+        .allow_sending_without_reply(true);
+    match keyboard {
+        Some(keyboard) => request.reply_markup(keyboard),
+        None => request,
+    }
+}
+
+/// Renders the markdown `text` as Telegram HTML and sends it as a reply,
+/// with `keyboard` attached when given. When Telegram rejects the generated
+/// entities the raw text is sent instead, so a formatting problem never
+/// loses the reply.
+pub async fn send_formatted_reply(
+    bot: &Bot,
+    to: Destination,
+    text: &str,
+    reply_to: MessageId,
+    keyboard: Option<InlineKeyboardMarkup>,
+) -> Result<Message, RequestError> {
+    let result = new_reply(bot, to, markdown::to_telegram_html(text), reply_to, keyboard.clone())
+        .parse_mode(ParseMode::Html)
+        .await;
+
+    match result {
+        Err(err) if is_entity_error(&err) => new_reply(bot, to, text.to_string(), reply_to, keyboard).await,
+        other => other,
+    }
+}
+
+/// Sends the markdown `text` as formatted replies to `reply_to`, split into
+/// as many messages as Telegram's length limit requires, and returns them.
+/// `keyboard` goes on the last part.
+pub async fn send_long_reply(
+    bot: &Bot,
+    to: Destination,
+    text: &str,
+    reply_to: MessageId,
+    keyboard: Option<InlineKeyboardMarkup>,
+) -> Result<Vec<Message>, RequestError> {
+    let parts = split_markdown(text);
+    let last = parts.len().saturating_sub(1);
+    let mut sent = Vec::new();
+    for (index, part) in parts.into_iter().enumerate() {
+        let keyboard = keyboard.clone().filter(|_| index == last);
+        sent.push(send_formatted_reply(bot, to, &part, reply_to, keyboard).await?);
+    }
+    Ok(sent)
+}
+
+/// Sends a message that is expected to be edited later (e.g. while a
+/// streamed answer is still arriving) and returns it.
+pub async fn send_draft(
+    bot: &Bot,
+    to: Destination,
+    text: impl Into<String>,
+    reply_to: MessageId,
+) -> Result<Message, RequestError> {
+    send_reply(bot, to, text, reply_to).await
+}
+
+/// Replaces the text of a message. Its inline keyboard is replaced by
+/// `keyboard`, or removed when there is none.
+pub async fn edit_text(
+    bot: &Bot,
+    chat_id: ChatId,
+    message_id: MessageId,
+    text: impl Into<String>,
+    keyboard: Option<InlineKeyboardMarkup>,
+) -> Result<(), RequestError> {
+    let mut request = bot
+        .edit_message_text(chat_id, message_id, text.into())
+        .disable_web_page_preview(true);
+    if let Some(keyboard) = keyboard {
+        request = request.reply_markup(keyboard);
+    }
+    match request.await {
+        Ok(_) | Err(RequestError::Api(ApiError::MessageNotModified)) => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+/// Formatted counterpart of [`edit_text`] with the same plain-text fallback
+/// as [`send_formatted_reply`].
+pub async fn edit_formatted(
+    bot: &Bot,
+    chat_id: ChatId,
+    message_id: MessageId,
+    text: &str,
+    keyboard: Option<InlineKeyboardMarkup>,
+) -> Result<(), RequestError> {
+    let mut request = bot
+        .edit_message_text(chat_id, message_id, markdown::to_telegram_html(text))
+        .parse_mode(ParseMode::Html)
+        .disable_web_page_preview(true);
+    if let Some(keyboard) = keyboard.clone() {
+        request = request.reply_markup(keyboard);
+    }
+
+    match request.await {
+        Ok(_) | Err(RequestError::Api(ApiError::MessageNotModified)) => Ok(()),
+        Err(err) if is_entity_error(&err) => edit_text(bot, chat_id, message_id, text, keyboard).await,
+        Err(err) => Err(err),
+    }
+}
+
+/// Sends a message with an inline keyboard.
+pub async fn send_menu(
+    bot: &Bot,
+    to: Destination,
+    text: impl Into<String>,
+    keyboard: InlineKeyboardMarkup,
+    reply_to: MessageId,
+) -> Result<(), RequestError> {
+    new_message(bot, to, text.into())
+        .reply_markup(keyboard)
+        .reply_to_message_id(reply_to)
+        .allow_sending_without_reply(true)
         .await?;
     Ok(())
 }
 
-pub async fn send_error(bot: &Bot, chat_id: ChatId, text: impl Into<String>) -> Result<(), RequestError> {
-    send_text(bot, chat_id, text).await
+/// Replaces the text of a menu message; the keyboard is removed unless a
+/// new one is given.
+pub async fn edit_menu(
+    bot: &Bot,
+    message: &Message,
+    text: impl Into<String>,
+    keyboard: Option<InlineKeyboardMarkup>,
+) -> Result<(), RequestError> {
+    let mut request = bot.edit_message_text(message.chat.id, message.id, text.into());
+    if let Some(keyboard) = keyboard {
+        request = request.reply_markup(keyboard);
+    }
+    match request.await {
+        Ok(_) | Err(RequestError::Api(ApiError::MessageNotModified)) => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+pub async fn delete_message(bot: &Bot, chat_id: ChatId, message_id: MessageId) -> Result<(), RequestError> {
+    bot.delete_message(chat_id, message_id).await?;
+    Ok(())
+}
+
+/// Stops the loading indicator on a pressed button, optionally showing a
+/// short notification.
+pub async fn answer_callback(bot: &Bot, query: &CallbackQuery, text: Option<&str>) -> Result<(), RequestError> {
+    let mut request = bot.answer_callback_query(&query.id);
+    if let Some(text) = text {
+        request = request.text(text);
+    }
+    request.await?;
+    Ok(())
+}
+
+/// Whether Telegram rejected the rendered HTML, so that the raw text is
+/// worth sending instead.
+fn is_entity_error(error: &RequestError) -> bool {
+    match error {
+        RequestError::Api(ApiError::CantParseEntities | ApiError::MessageIsTooLong | ApiError::EditedMessageIsTooLong) => {
+            true
+        }
+        RequestError::Api(ApiError::Unknown(description)) => description.contains("can't parse entities"),
+        _ => false,
+    }
+}
+
+/// Sends an error notice, as a reply to the message that caused it when
+/// there is one.
+pub async fn send_error(
+    bot: &Bot,
+    to: Destination,
+    text: impl Into<String>,
+    reply_to: Option<MessageId>,
+) -> Result<Message, RequestError> {
+    match reply_to {
+        Some(reply_to) => send_reply(bot, to, text, reply_to).await,
+        None => new_message(bot, to, text.into()).await,
+    }
+}
+
+/// Splits the markdown `text` into parts that still fit in a Telegram
+/// message once rendered. Rendering can make text longer (rules, list
+/// numbers, escaped characters), so a part that comes out too long is split
+/// again with a limit shrunk by the same ratio.
+pub fn split_markdown(text: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    split_rendered(text, TELEGRAM_MESSAGE_LIMIT, &mut parts);
+    parts
+}
+
+fn split_rendered(text: &str, limit: usize, parts: &mut Vec<String>) {
+    for part in split_message(text, limit) {
+        let length = utf16_len(&part);
+        let rendered = utf16_len(&markdown::to_telegram_html(&part));
+        if rendered <= TELEGRAM_MESSAGE_LIMIT || length <= 1 {
+            parts.push(part);
+            continue;
+        }
+        let smaller = (length * TELEGRAM_MESSAGE_LIMIT / rendered).clamp(1, length - 1);
+        split_rendered(&part, smaller, parts);
+    }
+}
+
+/// Splits `text` into parts of at most `limit` UTF-16 code units. Text that
+/// fits comes back unchanged. Otherwise cuts prefer paragraph, then line,
+/// sentence, word and finally character boundaries, and everything between
+/// two cuts is kept as written. A fenced code block (```` ``` ```` or `~~~`)
+/// that has to be cut is closed at the end of the part and re-opened with
+/// the same fence line at the start of the next one.
+pub fn split_message(text: &str, limit: usize) -> Vec<String> {
+    if text.trim().is_empty() {
+        return Vec::new();
+    }
+    if utf16_len(text) <= limit {
+        return vec![text.to_string()];
+    }
+
+    let layout = Layout::new(text);
+    let mut parts = Vec::new();
+    let mut start = layout.skip_blank_lines(0);
+    while start < text.len() {
+        let reopen = layout
+            .fence_at(start)
+            .map(|fence| format!("{}\n", fence.line))
+            .unwrap_or_default();
+        let budget = limit.saturating_sub(utf16_len(&reopen));
+        if layout.width(start, text.len()) <= budget {
+            parts.push(format!("{reopen}{}", &text[start..]));
+            break;
+        }
+
+        let end = layout.cut(start, budget);
+        parts.push(format!("{reopen}{}", layout.part(start, end)));
+        start = layout.skip_blank_lines(end);
+    }
+
+    parts.retain(|part| !part.trim().is_empty());
+    parts
+}
+
+/// An opening code fence line: ```` ``` ```` or `~~~`, at least three long.
+#[derive(Clone, Copy)]
+struct Fence<'a> {
+    line: &'a str,
+    marker: char,
+    len: usize,
+}
+
+impl<'a> Fence<'a> {
+    fn opened_by(line: &'a str) -> Option<Self> {
+        let trimmed = line.trim_start();
+        let marker = trimmed.chars().next().filter(|marker| matches!(marker, '`' | '~'))?;
+        let len = trimmed.chars().take_while(|&ch| ch == marker).count();
+        // A backtick fence's info string cannot contain backticks.
+        if len < 3 || (marker == '`' && trimmed[len..].contains('`')) {
+            return None;
+        }
+        Some(Self {
+            line: line.trim_end(),
+            marker,
+            len,
+        })
+    }
+
+    fn is_closed_by(&self, line: &str) -> bool {
+        let trimmed = line.trim();
+        trimmed.len() >= self.len && trimmed.chars().all(|ch| ch == self.marker)
+    }
+
+    fn closing(&self) -> String {
+        self.marker.to_string().repeat(self.len)
+    }
+}
+
+struct Line<'a> {
+    start: usize,
+    end: usize,
+    /// Fence left open by the lines before this one.
+    fence_before: Option<Fence<'a>>,
+    /// Whether this line opens or closes a fence.
+    is_fence: bool,
+    is_blank: bool,
+}
+
+/// The lines of a message being split, with UTF-16 offsets for measuring
+/// any slice of it.
+struct Layout<'a> {
+    text: &'a str,
+    lines: Vec<Line<'a>>,
+    /// UTF-16 length of `text[..index]` for every char boundary `index`.
+    utf16_offsets: Vec<usize>,
+}
+
+impl<'a> Layout<'a> {
+    fn new(text: &'a str) -> Self {
+        let mut lines = Vec::new();
+        let mut open: Option<Fence> = None;
+        let mut start = 0;
+        for line in text.split_inclusive('\n') {
+            let (is_fence, open_after) = match open {
+                Some(fence) if fence.is_closed_by(line) => (true, None),
+                Some(fence) => (false, Some(fence)),
+                None => match Fence::opened_by(line) {
+                    Some(fence) => (true, Some(fence)),
+                    None => (false, None),
+                },
+            };
+            lines.push(Line {
+                start,
+                end: start + line.len(),
+                fence_before: open,
+                is_fence,
+                is_blank: line.trim().is_empty(),
+            });
+            open = open_after;
+            start += line.len();
+        }
+
+        let mut utf16_offsets = vec![0; text.len() + 1];
+        let mut width = 0;
+        for (index, ch) in text.char_indices() {
+            utf16_offsets[index] = width;
+            width += ch.len_utf16();
+        }
+        utf16_offsets[text.len()] = width;
+
+        Self {
+            text,
+            lines,
+            utf16_offsets,
+        }
+    }
+
+    fn width(&self, start: usize, end: usize) -> usize {
+        self.utf16_offsets[end] - self.utf16_offsets[start]
+    }
+
+    fn line_at(&self, position: usize) -> usize {
+        self.lines.partition_point(|line| line.start <= position).saturating_sub(1)
+    }
+
+    /// The fence a cut at `position` would leave open.
+    fn fence_at(&self, position: usize) -> Option<Fence<'a>> {
+        let line = &self.lines[self.line_at(position)];
+        if line.is_fence && position != line.start {
+            return None;
+        }
+        line.fence_before
+    }
+
+    /// What has to follow a part ending at `end` to close an open fence.
+    fn closing(&self, end: usize) -> Option<String> {
+        let fence = self.fence_at(end)?;
+        let newline = if self.text[..end].ends_with('\n') { "" } else { "\n" };
+        Some(format!("{newline}{}", fence.closing()))
+    }
+
+    /// The part from `start` to `end`: closed if it ends inside a code
+    /// block, without trailing whitespace otherwise.
+    fn part(&self, start: usize, end: usize) -> String {
+        let text = &self.text[start..end];
+        match self.closing(end) {
+            Some(closing) => format!("{text}{closing}"),
+            None => text.trim_end().to_string(),
+        }
+    }
+
+    fn fits(&self, start: usize, end: usize, budget: usize) -> bool {
+        let closing = self.closing(end).map_or(0, |closing| utf16_len(&closing));
+        self.width(start, end) + closing <= budget
+    }
+
+    /// Whether a paragraph or a code block starts at line `index`.
+    fn starts_block(&self, index: usize) -> bool {
+        let line = &self.lines[index];
+        let previous = &self.lines[index - 1];
+        line.fence_before.is_none() && (line.is_blank || previous.is_blank || line.is_fence || previous.is_fence)
+    }
+
+    /// Where the part starting at `start` should end so that it fits in
+    /// `budget`, preferring the coarsest boundary that does.
+    fn cut(&self, start: usize, budget: usize) -> usize {
+        let first = self.line_at(start);
+        let rest = &self.text[start..self.lines[first].end];
+        let last_fitting = |candidates: &mut dyn Iterator<Item = usize>| {
+            candidates
+                .filter(|&end| end > start)
+                .take_while(|&end| self.width(start, end) <= budget)
+                .filter(|&end| self.fits(start, end, budget))
+                .last()
+        };
+
+        let boundaries = first + 1..self.lines.len();
+        last_fitting(
+            &mut boundaries
+                .clone()
+                .filter(|&index| self.starts_block(index))
+                .map(|index| self.lines[index].start),
+        )
+        .or_else(|| last_fitting(&mut boundaries.map(|index| self.lines[index].start)))
+        .or_else(|| {
+            last_fitting(
+                &mut rest
+                    .char_indices()
+                    .zip(rest.chars().skip(1))
+                    .filter(|((_, ch), next)| matches!(ch, '.' | '!' | '?') && next.is_whitespace())
+                    .map(|((index, ch), next)| start + index + ch.len_utf8() + next.len_utf8()),
+            )
+        })
+        .or_else(|| {
+            last_fitting(
+                &mut rest
+                    .char_indices()
+                    .filter(|(_, ch)| ch.is_whitespace())
+                    .map(|(index, ch)| start + index + ch.len_utf8()),
+            )
+        })
+        .or_else(|| last_fitting(&mut rest.char_indices().skip(1).map(|(index, _)| start + index)))
+        .unwrap_or_else(|| start + rest.chars().next().map_or(1, char::len_utf8))
+    }
+
+    /// Skips blank lines at `position` unless they are inside a code block.
+    fn skip_blank_lines(&self, mut position: usize) -> usize {
+        while position < self.text.len() && self.fence_at(position).is_none() {
+            let rest = &self.text[position..];
+            let line_end = rest.find('\n').map_or(rest.len(), |index| index + 1);
+            if !rest[..line_end].trim().is_empty() {
+                break;
+            }
+            position += line_end;
+        }
+        position
+    }
+}
+
+fn utf16_len(text: &str) -> usize {
+    text.encode_utf16().count()
+}
+
+/// Returns the Bot API download URL of a file. The URL contains the bot
+/// token.
+pub fn file_url(bot: &Bot, file: &File) -> String {
+    format!(
+        "{}/file/bot{}/{}",
+        bot.api_url().as_str().trim_end_matches('/'),
+        bot.token(),
+        file.path
+    )
+}
+
+/// Downloads a file and returns it inlined as a base64 `data:` URL.
+pub async fn file_data_url(bot: &Bot, file: &File, mime_type: &str) -> Result<String, DownloadError> {
+    let mut bytes = Vec::with_capacity(file.meta.size as usize);
+    bot.download_file(&file.path, &mut bytes).await?;
+    Ok(format!("data:{mime_type};base64,{}", STANDARD.encode(bytes)))
 }
 
 pub struct TypingIndicator {
@@ -24,7 +532,7 @@ pub struct TypingIndicator {
 }
 
 impl TypingIndicator {
-    pub fn start(bot: Bot, chat_id: ChatId) -> Self {
+    pub fn start(bot: Bot, to: Destination) -> Self {
         let (stop_tx, mut stop_rx) = oneshot::channel();
 
         tokio::spawn(async move {
@@ -32,7 +540,11 @@ impl TypingIndicator {
                 tokio::select! {
                     _ = &mut stop_rx => break,
                     _ = async {
-                        let _ = bot.send_chat_action(chat_id, ChatAction::Typing).await;
+                        let mut action = bot.send_chat_action(to.chat_id, ChatAction::Typing);
+                        if let Some(thread_id) = to.thread_id {
+                            action = action.message_thread_id(thread_id);
+                        }
+                        let _ = action.await;
                         tokio::time::sleep(Duration::from_secs(4)).await;
                     } => {}
                 }
@@ -52,3 +564,82 @@ impl Drop for TypingIndicator {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn code_block(fence: &str, closing: &str, lines: usize) -> String {
+        let code: String = (0..lines).map(|index| format!("    let value_{index} = {index};\n")).collect();
+        format!("Some intro.\n\n{fence}\n{code}{closing}\n\nDone.")
+    }
+
+    fn assert_reopened(parts: &[String], fence: &str, closing: &str, limit: usize, lines: usize) {
+        assert!(parts.len() > 2);
+        assert!(parts.iter().all(|part| utf16_len(part) <= limit));
+        assert_eq!(parts[0], "Some intro.");
+        let (last, middle) = parts[1..].split_last().unwrap();
+        for part in middle {
+            assert!(part.starts_with(&format!("{fence}\n")), "{part:?}");
+            assert!(part.ends_with(&format!("\n{closing}")), "{part:?}");
+        }
+        assert!(last.starts_with(&format!("{fence}\n")), "{last:?}");
+        assert!(last.ends_with(&format!("\n{closing}\n\nDone.")), "{last:?}");
+
+        let code_lines: Vec<&str> = parts[1..]
+            .iter()
+            .flat_map(|part| part.lines())
+            .filter(|line| line.starts_with("    let"))
+            .collect();
+        let expected: Vec<String> = (0..lines).map(|index| format!("    let value_{index} = {index};")).collect();
+        assert_eq!(code_lines, expected);
+    }
+
+    #[test]
+    fn text_that_fits_comes_back_unchanged() {
+        let text = "  indented first line\n\n\n\nafter blank lines\n```\ncode right after\n```\n~~~\nmore\n~~~ ";
+        assert_eq!(split_message(text, TELEGRAM_MESSAGE_LIMIT), vec![text.to_string()]);
+    }
+
+    #[test]
+    fn reopens_backtick_fences() {
+        let text = code_block("```rust", "```", 30);
+        assert_reopened(&split_message(&text, 200), "```rust", "```", 200, 30);
+    }
+
+    #[test]
+    fn reopens_tilde_fences() {
+        let text = code_block("~~~~ python", "~~~~", 30);
+        assert_reopened(&split_message(&text, 200), "~~~~ python", "~~~~", 200, 30);
+    }
+
+    #[test]
+    fn keeps_indentation_after_a_cut() {
+        let text = format!("{}\n\n\n  - nested item\n  - another one", "word ".repeat(10).trim_end());
+        assert_eq!(
+            split_message(&text, 60),
+            vec!["word ".repeat(10).trim_end().to_string(), "  - nested item\n  - another one".to_string()]
+        );
+    }
+
+    #[test]
+    fn splits_markdown_by_its_rendered_length() {
+        let text = "a & b\n\n---\n\n".repeat(600);
+        assert!(utf16_len(&markdown::to_telegram_html(&split_message(&text, TELEGRAM_MESSAGE_LIMIT)[0])) > TELEGRAM_MESSAGE_LIMIT);
+        let parts = split_markdown(&text);
+        assert!(parts.len() > 2);
+        assert!(parts
+            .iter()
+            .all(|part| utf16_len(&markdown::to_telegram_html(part)) <= TELEGRAM_MESSAGE_LIMIT));
+    }
+
+    #[test]
+    fn measures_the_limit_in_utf16_code_units() {
+        let text = ["😀😀😀"; 10].join(" ");
+        assert_eq!(text.chars().count(), 39);
+        let parts = split_message(&text, 20);
+        assert!(parts.iter().all(|part| utf16_len(part) <= 20));
+        assert!(parts.len() > 2);
+        assert_eq!(parts.join(" "), text);
+    }
+}